@@ -0,0 +1,329 @@
+use std::collections::{HashMap, VecDeque};
+
+use macroquad::prelude::{vec2, Rect, Vec2};
+
+use crate::{
+    components::{Map, Prop, Spawn, Tile},
+    level::{generate_dungeon, GeneratorParams},
+    level_utils::{adjecent_idxs, hash, is_walkable, refresh_draw_tiles, surrounding_idxs},
+    TILE_SIZE,
+};
+
+/// Width and height of a chunk in tiles.
+pub const CHUNK_SIZE: usize = 32;
+// tiles along the border of a chunk that rooms stay out of, so only the
+// corridors crossing into the neighbours touch the border
+const CHUNK_MARGIN: usize = 2;
+// at most this many chunks are generated per frame, so streaming doesn't
+// stall the viewer
+const CHUNKS_PER_FRAME: usize = 2;
+
+pub type ChunkCoord = (i32, i32);
+
+fn chunk_seed(seed: u64, coord: ChunkCoord) -> u64 {
+    hash(&[seed, coord.0 as i64 as u64, coord.1 as i64 as u64])
+}
+
+/**
+ * Position along an edge where the corridor between two neighbouring chunks
+ * crosses it. The edge is named by the chunk left of or above it, so both
+ * chunks come up with the same crossing and their corridors meet.
+ */
+fn crossing(seed: u64, coord: ChunkCoord, vertical: bool) -> usize {
+    let h = hash(&[chunk_seed(seed, coord), vertical as u64]);
+    CHUNK_MARGIN + (h % (CHUNK_SIZE - 2 * CHUNK_MARGIN) as u64) as usize
+}
+
+/**
+ * The border cell of every edge crossing of a chunk along with the direction
+ * pointing into the chunk.
+ */
+fn crossings(seed: u64, (x, y): ChunkCoord) -> [(Vec2, Vec2); 4] {
+    let last = (CHUNK_SIZE - 1) as f32;
+    [
+        (
+            vec2(0.0, crossing(seed, (x - 1, y), true) as f32),
+            vec2(1.0, 0.0),
+        ),
+        (
+            vec2(last, crossing(seed, (x, y), true) as f32),
+            vec2(-1.0, 0.0),
+        ),
+        (
+            vec2(crossing(seed, (x, y - 1), false) as f32, 0.0),
+            vec2(0.0, 1.0),
+        ),
+        (
+            vec2(crossing(seed, (x, y), false) as f32, last),
+            vec2(0.0, -1.0),
+        ),
+    ]
+}
+
+/**
+ * Params for the dungeon inside a single chunk. Chunks are small and have no
+ * floors, so they get a few rooms and no vaults, locks or secret rooms.
+ */
+fn chunk_params(params: &GeneratorParams) -> GeneratorParams {
+    GeneratorParams {
+        min_rooms: 2,
+        max_rooms: 4,
+        vaults: false,
+        lock_count: 0,
+        secret_rooms: 0,
+        floors: 1,
+        ..*params
+    }
+}
+
+/**
+ * Digs the shortest corridor from any of the sources to a cell that counts as
+ * reached. Apart from the sources the corridor stays out of the margin, so the
+ * border only opens up at the crossings. Returns false if there is no way.
+ */
+fn dig(map: &mut Map, sources: &[usize], reached: impl Fn(&Map, usize) -> bool) -> bool {
+    let margin = CHUNK_MARGIN as f32;
+    let inside = |p: Vec2| {
+        p.x >= margin && p.y >= margin && p.x < map.size.x - margin && p.y < map.size.y - margin
+    };
+    let mut parents: Vec<Option<usize>> = vec![None; map.tiles.len()];
+    let mut visited = vec![false; map.tiles.len()];
+    let mut queue = VecDeque::new();
+    for source in sources {
+        visited[*source] = true;
+        queue.push_back(*source);
+    }
+
+    while let Some(idx) = queue.pop_front() {
+        if reached(map, idx) {
+            let mut cell = parents[idx];
+            while let Some(c) = cell {
+                if !is_walkable(&map.tiles[c]) {
+                    map.tiles[c] = Tile::Floor;
+                }
+                cell = parents[c];
+            }
+            return true;
+        }
+
+        let p = map.idx_to_vec2(idx);
+        for dir in [
+            vec2(1.0, 0.0),
+            vec2(-1.0, 0.0),
+            vec2(0.0, 1.0),
+            vec2(0.0, -1.0),
+        ] {
+            let next = p + dir;
+            if !inside(next) {
+                continue;
+            }
+            let next = map.idx(next);
+            if !visited[next] {
+                visited[next] = true;
+                parents[next] = Some(idx);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    false
+}
+
+/**
+ * Walkable cells that can be reached from start.
+ */
+fn flood(map: &Map, start: usize) -> Vec<bool> {
+    let mut reached = vec![false; map.tiles.len()];
+    let mut queue = VecDeque::from([start]);
+    reached[start] = true;
+    while let Some(idx) = queue.pop_front() {
+        for adj in adjecent_idxs(map, idx) {
+            if !reached[adj] && is_walkable(&map.tiles[adj]) {
+                reached[adj] = true;
+                queue.push_back(adj);
+            }
+        }
+    }
+
+    reached
+}
+
+/**
+ * Digs corridors from every part of the chunk that can't be reached from
+ * start to the closest part that can, so the whole chunk is connected to the
+ * crossings.
+ */
+fn connect_regions(map: &mut Map, start: usize) {
+    loop {
+        let reached = flood(map, start);
+        let rest = (0..map.tiles.len()).find(|i| is_walkable(&map.tiles[*i]) && !reached[*i]);
+        let region = match rest {
+            Some(rest) => flood(map, rest),
+            None => return,
+        };
+        let sources = (0..map.tiles.len())
+            .filter(|i| region[*i])
+            .collect::<Vec<usize>>();
+        if !dig(map, &sources, |_, i| reached[i]) {
+            return;
+        }
+    }
+}
+
+/**
+ * Surrounds everything walkable with walls.
+ */
+fn place_walls(map: &mut Map) {
+    for idx in 0..map.tiles.len() {
+        if !is_walkable(&map.tiles[idx]) {
+            continue;
+        }
+
+        for s in surrounding_idxs(map, idx) {
+            if map.tiles[s] == Tile::Dirt {
+                map.tiles[s] = Tile::Wall;
+            }
+        }
+    }
+}
+
+/**
+ * Generates the chunk at coord of the world with the given seed. The same
+ * seed and coord always produce the same chunk, no matter which chunks were
+ * generated before. The rooms are generated like a small dungeon inside the
+ * margin, without stairs, and connected to the corridors crossing each edge.
+ */
+pub fn generate_chunk(seed: u64, coord: ChunkCoord, params: &GeneratorParams) -> Map {
+    let inner_size = (CHUNK_SIZE - 2 * CHUNK_MARGIN) as f32;
+    let mut inner = Map::new(vec2(inner_size, inner_size));
+    generate_dungeon(
+        &mut inner,
+        chunk_seed(seed, coord),
+        &chunk_params(params),
+        None,
+    );
+
+    let mut map = Map::new(vec2(CHUNK_SIZE as f32, CHUNK_SIZE as f32));
+    let offset = vec2(CHUNK_MARGIN as f32, CHUNK_MARGIN as f32);
+    let moved = |idx: usize| map.idx(inner.idx_to_vec2(idx) + offset);
+    let mut tiles = map.tiles.clone();
+    let mut materials = map.materials.clone();
+    for (idx, tile) in inner.tiles.iter().enumerate() {
+        materials[moved(idx)] = inner.materials[idx];
+        tiles[moved(idx)] = match tile {
+            // walls are put back once the crossings are dug
+            Tile::Wall => Tile::Dirt,
+            Tile::StairsUp | Tile::StairsDown => Tile::Floor,
+            tile => *tile,
+        };
+    }
+    let spawns = inner
+        .spawns
+        .iter()
+        .map(|s| Spawn {
+            kind: s.kind,
+            pos: moved(s.pos),
+        })
+        .collect();
+    let props = inner
+        .props
+        .iter()
+        .map(|p| Prop {
+            kind: p.kind,
+            pos: moved(p.pos),
+        })
+        .collect();
+    map.tiles = tiles;
+    map.materials = materials;
+    map.seed = inner.seed;
+    map.spawns = spawns;
+    map.props = props;
+    map.rooms = inner
+        .rooms
+        .into_iter()
+        .map(|mut room| {
+            room.pos += offset;
+            room
+        })
+        .collect();
+
+    if !map.tiles.iter().any(is_walkable) {
+        let center = map.idx(map.size / 2.0);
+        map.tiles[center] = Tile::Floor;
+    }
+    let crossings = crossings(seed, coord);
+    for (pos, inward) in crossings {
+        let border = map.idx(pos);
+        map.tiles[border] = Tile::Floor;
+        let start = map.idx(pos + inward);
+        dig(&mut map, &[start], |map, i| is_walkable(&map.tiles[i]));
+    }
+    let start = map.idx(crossings[0].0);
+    connect_regions(&mut map, start);
+    place_walls(&mut map);
+    refresh_draw_tiles(&mut map);
+
+    map
+}
+
+/**
+ * Open-ended world made of chunks that are generated when they come into view
+ * and dropped again once they're far away.
+ */
+pub struct ChunkedWorld {
+    pub seed: u64,
+    pub params: GeneratorParams,
+    pub chunks: HashMap<ChunkCoord, Map>,
+}
+
+impl ChunkedWorld {
+    pub fn new(seed: u64, params: GeneratorParams) -> ChunkedWorld {
+        ChunkedWorld {
+            seed,
+            params,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /**
+     * The first and last chunk overlapping an area given in world pixels.
+     */
+    pub fn chunks_in(area: Rect) -> (ChunkCoord, ChunkCoord) {
+        let chunk = CHUNK_SIZE as f32 * TILE_SIZE;
+        let coord = |p: Vec2| ((p.x / chunk).floor() as i32, (p.y / chunk).floor() as i32);
+        (
+            coord(area.point()),
+            coord(area.point() + area.size() - Vec2::ONE),
+        )
+    }
+
+    /**
+     * Loads the chunks in view plus a ring around it, closest to the middle
+     * of the view first, and unloads the chunks further out than that.
+     */
+    pub fn stream(&mut self, view: Rect) {
+        let (min, max) = ChunkedWorld::chunks_in(view);
+        let (min, max) = ((min.0 - 1, min.1 - 1), (max.0 + 1, max.1 + 1));
+        let middle = vec2((min.0 + max.0) as f32, (min.1 + max.1) as f32) / 2.0;
+
+        let mut missing = (min.1..=max.1)
+            .flat_map(|y| (min.0..=max.0).map(move |x| (x, y)))
+            .filter(|coord| !self.chunks.contains_key(coord))
+            .collect::<Vec<ChunkCoord>>();
+        missing.sort_by(|a, b| {
+            let a = vec2(a.0 as f32, a.1 as f32).distance_squared(middle);
+            let b = vec2(b.0 as f32, b.1 as f32).distance_squared(middle);
+            a.total_cmp(&b)
+        });
+        for coord in missing.into_iter().take(CHUNKS_PER_FRAME) {
+            let chunk = generate_chunk(self.seed, coord, &self.params);
+            self.chunks.insert(coord, chunk);
+        }
+
+        // keep one more ring loaded, so moving back and forth over a chunk
+        // border doesn't generate the same chunks over and over
+        self.chunks.retain(|(x, y), _| {
+            *x >= min.0 - 1 && *x <= max.0 + 1 && *y >= min.1 - 1 && *y <= max.1 + 1
+        });
+    }
+}
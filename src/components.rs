@@ -1,74 +1,235 @@
-use crate::{draw::Drawable, GAME_HEIGHT, GAME_WIDTH};
+use crate::{
+    chunks::ChunkedWorld,
+    draw::{Drawable, LIGHT},
+    dungeon::MultiLevelDungeon,
+    editor::Editor,
+    history::{History, Snapshot, HISTORY_CAPACITY},
+    level::{GenerationTrace, GeneratorParams},
+    level_utils::hash,
+    pathfinding::{PathCosts, PathTool},
+    play::Exploration,
+    secrets::hide_secrets,
+    tileset::tileset,
+    ui::Panel,
+    GAME_HEIGHT, GAME_WIDTH, TILE_SIZE,
+};
+use std::{
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use derive_more::From;
 use macroquad::{
+    camera::Camera2D,
+    color::Color,
+    logging::warn,
     prelude::{vec2, Vec2},
     texture::{draw_texture_ex, Texture2D},
-    time::get_frame_time,
-    window::{screen_height, screen_width},
+    time::{get_frame_time, get_time},
+    window::{get_internal_gl, screen_height, screen_width},
 };
 
 pub static ROOM_SIZES: [Vec2; 2] = [vec2(5.0, 5.0), vec2(5.0, 7.0)];
-
-#[derive(PartialEq)]
+pub static SMALL_ROOM_SIZES: [Vec2; 3] = [vec2(3.0, 3.0), vec2(3.0, 4.0), vec2(4.0, 3.0)];
+pub static LARGE_ROOM_SIZES: [Vec2; 3] = [vec2(7.0, 7.0), vec2(9.0, 7.0), vec2(7.0, 9.0)];
+pub static MIXED_ROOM_SIZES: [Vec2; 5] = [
+    vec2(3.0, 3.0),
+    vec2(5.0, 5.0),
+    vec2(5.0, 7.0),
+    vec2(7.0, 5.0),
+    vec2(9.0, 7.0),
+];
+
+/**
+ * What the walls of a room are built from. Corridors are stone.
+ */
+#[derive(Clone, Copy, PartialEq)]
 pub enum WallMaterial {
     Stone,
     Brick,
 }
 
-// Ends
-pub const WALL_RIGHT_END: Vec2 = vec2(3.0, 1.0);
-pub const WALL_LEFT_END: Vec2 = vec2(2.0, 2.0);
-pub const WALL_TOP_END: Vec2 = vec2(1.0, 2.0);
-pub const WALL_BOTTOM_END: Vec2 = vec2(0.0, 2.0);
-
-pub const WALL_CROSS: Vec2 = vec2(5.0, 0.0);
-pub const WALL_VERT_LINE: Vec2 = vec2(4.0, 1.0);
-pub const WALL_HOR_LINE: Vec2 = vec2(3.0, 0.0);
+impl WallMaterial {
+    pub const ALL: [WallMaterial; 2] = [WallMaterial::Stone, WallMaterial::Brick];
 
-// Ts
-pub const WALL_UPRIGHT_T: Vec2 = vec2(0.0, 0.0);
-pub const WALL_DOWNRIGHT_T: Vec2 = vec2(1.0, 1.0);
-pub const WALL_RIGHT_LYING_T: Vec2 = vec2(1.0, 0.0);
-pub const WALL_LEFT_LYING_T: Vec2 = vec2(0.0, 1.0);
-
-// Corners
-pub const WALL_TOP_LEFT_CORNER: Vec2 = vec2(2.0, 0.0);
-pub const WALL_TOP_RIGHT_CORNER: Vec2 = vec2(4.0, 0.0);
-pub const WALL_BOTTOM_LEFT_CORNER: Vec2 = vec2(2.0, 1.0);
-pub const WALL_BOTTOM_RIGHT_CORNER: Vec2 = vec2(4.0, 2.0);
+    pub fn name(&self) -> &'static str {
+        match self {
+            WallMaterial::Stone => "stone",
+            WallMaterial::Brick => "brick",
+        }
+    }
+}
 
-#[derive(PartialEq)]
-pub enum WallType {
-    Cross,
-    HorinzontalCenter,
-    HorisonalLeftEnd,
-    HorisonalRightEnd,
-    VerticalCenter,
-    ReverseL,
-    L,
-    T,
-    ReverseT,
+pub trait Updateable {
+    fn update(&mut self, world: &World);
 }
 
-#[derive(PartialEq)]
-pub struct Wall {
-    pub material: WallMaterial,
-    pub wall_type: WallType,
-    pub atlas_pos: Vec2,
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scaling {
+    Free,
+    Integer,
 }
 
-pub trait Updateable {
-    fn update(&mut self, world: &World);
+/**
+ * Physical pixels per screen pixel. Screen sizes, the mouse and the default
+ * camera are all in screen pixels, so only code that has to line up with the
+ * physical ones needs this.
+ */
+pub fn dpi_scale() -> f32 {
+    // only reads the window's scale factor, nothing is drawn through it
+    unsafe { get_internal_gl() }.quad_context.dpi_scale()
 }
 
 pub struct CameraControl {
-    pub pos: Vec2,
+    /// World position in pixels shown in the middle of the screen.
+    pub target: Vec2,
     pub zoom: f32,
+    pub scaling: Scaling,
 }
+
+impl CameraControl {
+    /**
+     * World pixels per screen pixel. Integer scaling snaps the zoom so every
+     * texel covers a whole number of physical pixels, which keeps pixel art
+     * crisp on high-DPI screens too.
+     */
+    pub fn scale(&self) -> f32 {
+        self.scale_at(dpi_scale())
+    }
+
+    fn scale_at(&self, dpi: f32) -> f32 {
+        let physical = self.zoom / dpi;
+        match self.scaling {
+            Scaling::Free => self.zoom,
+            Scaling::Integer if physical >= 1.0 => physical.round() * dpi,
+            Scaling::Integer => dpi / (1.0 / physical).round(),
+        }
+    }
+
+    /**
+     * The part of the world that is visible, in world pixels. Computed from the
+     * current screen size, so it follows window resizes.
+     */
+    pub fn viewport(&self) -> macroquad::math::Rect {
+        let scale = self.scale();
+        let size = vec2(screen_width(), screen_height()) * scale;
+        let mut pos = self.target - size / 2.0;
+        if self.scaling == Scaling::Integer {
+            // world pixels per physical pixel
+            let pixel = scale / dpi_scale();
+            pos = (pos / pixel).round() * pixel;
+        }
+
+        macroquad::math::Rect {
+            x: pos.x,
+            y: pos.y,
+            w: size.x,
+            h: size.y,
+        }
+    }
+
+    pub fn camera2d(&self) -> Camera2D {
+        Camera2D::from_display_rect(self.viewport())
+    }
+
+    pub fn center_on(&mut self, target: Vec2) {
+        self.target = target;
+    }
+}
+
+/**
+ * Debug layers drawn on top of the map.
+ */
+pub struct Overlays {
+    pub grid: bool,
+    pub rooms: bool,
+    pub corridor_starts: bool,
+    pub dfs_order: bool,
+    pub doors: bool,
+    pub pruned_corridors: bool,
+    /// Shades the map by the light of torches instead of drawing it fully lit.
+    pub lighting: bool,
+}
+
+impl Overlays {
+    pub fn new() -> Overlays {
+        Overlays {
+            grid: true,
+            rooms: false,
+            corridor_starts: false,
+            dfs_order: false,
+            doors: false,
+            pruned_corridors: false,
+            lighting: false,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Mode {
+    View,
+    Edit,
+    Path,
+    Fov,
+    Chunks,
+    Play,
+}
+
+impl Mode {
+    pub fn next(&self) -> Mode {
+        match self {
+            Mode::View => Mode::Edit,
+            Mode::Edit => Mode::Path,
+            Mode::Path => Mode::Fov,
+            Mode::Fov => Mode::Chunks,
+            Mode::Chunks => Mode::Play,
+            Mode::Play => Mode::View,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::View => "VIEW",
+            Mode::Edit => "EDIT",
+            Mode::Path => "PATH",
+            Mode::Fov => "FOV",
+            Mode::Chunks => "CHUNKS",
+            Mode::Play => "PLAY",
+        }
+    }
+}
+
 pub struct World {
     pub size: Vec2,
     pub camera: CameraControl,
     pub map: Map,
+    pub mode: Mode,
+    pub editor: Editor,
+    pub seed: u64,
+    pub history: History,
+    pub params: GeneratorParams,
+    pub panel: Panel,
+    pub trace: GenerationTrace,
+    pub overlays: Overlays,
+    pub path_tool: PathTool,
+    pub path_costs: PathCosts,
+    pub sight_target: Option<Vec2>,
+    /// Every floor as it was when last shown. The current floor is edited in
+    /// map and trace and put back when another floor is shown.
+    pub dungeon: MultiLevelDungeon,
+    pub floor: usize,
+    /// Open-ended world shown in chunks mode, generated from the same seed.
+    pub chunks: ChunkedWorld,
+    pub style: MapStyle,
+    pub perspective: Perspective,
+    /// The player walking through the map in play mode. Starts over whenever
+    /// another map is shown.
+    pub exploration: Option<Exploration>,
+    /// Last message for the status line, with the time it was reported at.
+    pub status: Option<(String, f64)>,
+    /// The map with its secret rooms hidden, for the player perspective,
+    /// along with the revision of the map it was made from.
+    player_map: Option<(u64, Map)>,
 }
 
 impl World {
@@ -76,26 +237,243 @@ impl World {
         World {
             size: vec2(w, h),
             camera: CameraControl {
-                pos: vec2(screen_width() / 2.0, screen_height() / 2.0),
+                target: vec2(w, h) * TILE_SIZE / 2.0,
                 zoom: 1.0,
+                scaling: Scaling::Free,
             },
-            map: Map {
-                size: vec2(GAME_WIDTH, GAME_HEIGHT),
-                tiles: vec![Tile::Dirt; (GAME_WIDTH * GAME_HEIGHT) as usize],
-                draw_tiles: vec![
-                    AtlasTile::from(vec2(7.0, 0.0));
-                    (GAME_WIDTH * GAME_HEIGHT) as usize
-                ],
-            },
+            map: Map::new(vec2(GAME_WIDTH, GAME_HEIGHT)),
+            mode: Mode::View,
+            editor: Editor::new(),
+            seed: 0,
+            history: History::new(HISTORY_CAPACITY),
+            params: GeneratorParams::default(),
+            panel: Panel::new(),
+            trace: GenerationTrace::default(),
+            overlays: Overlays::new(),
+            path_tool: PathTool::new(),
+            path_costs: PathCosts::default(),
+            sight_target: None,
+            dungeon: MultiLevelDungeon::default(),
+            floor: 0,
+            chunks: ChunkedWorld::new(0, GeneratorParams::default()),
+            style: MapStyle::Tileset,
+            perspective: Perspective::Designer,
+            exploration: None,
+            status: None,
+            player_map: None,
+        }
+    }
+
+    /**
+     * The map as it's drawn from the current perspective. The player doesn't
+     * get to see secret rooms.
+     */
+    pub fn shown_map(&self) -> &Map {
+        match (self.perspective, &self.player_map) {
+            (Perspective::Player, Some((_, map))) => map,
+            _ => &self.map,
+        }
+    }
+
+    /**
+     * Hides the secret rooms of the map again if it changed since they were
+     * last hidden. Call before drawing.
+     */
+    pub fn update_shown_map(&mut self) {
+        if self.perspective != Perspective::Player {
+            return;
+        }
+        let stale = self
+            .player_map
+            .as_ref()
+            .is_none_or(|(revision, _)| *revision != self.map.revision);
+        if stale {
+            self.player_map = Some((self.map.revision, hide_secrets(&self.map)));
+        }
+    }
+
+    /**
+     * Shows a message in the status line for a few seconds and logs it.
+     */
+    pub fn report(&mut self, message: String) {
+        warn!("{}", message);
+        self.status = Some((message, get_time()));
+    }
+
+    /**
+     * Replaces the map with a freshly generated dungeon, keeping the old one in
+     * the history.
+     */
+    pub fn regenerate(&mut self, seed: u64) {
+        self.checkpoint();
+        self.seed = seed;
+        self.generate();
+    }
+
+    /**
+     * Generates all floors from the current seed and params and shows the
+     * first one.
+     */
+    pub fn generate(&mut self) {
+        self.size = self.map.size;
+        self.dungeon = MultiLevelDungeon::generate(self.map.size, self.seed, &self.params);
+        self.show_floor(0);
+        self.chunks = ChunkedWorld::new(self.seed, self.params);
+        self.exploration = None;
+    }
+
+    /**
+     * Copies a floor out of the dungeon into map and trace to show it.
+     */
+    fn show_floor(&mut self, floor: usize) {
+        if floor < self.dungeon.levels.len() {
+            self.map = (*self.dungeon.levels[floor]).clone();
+            self.trace = (*self.dungeon.traces[floor]).clone();
+            self.floor = floor;
+        }
+    }
+
+    /**
+     * Puts the current floor back into the dungeon. Call after replacing the
+     * map outright, so changing floors doesn't bring the old one back.
+     */
+    pub fn store_floor(&mut self) {
+        if self.floor < self.dungeon.levels.len() {
+            self.dungeon.levels[self.floor] = Rc::new(self.map.clone());
+            self.dungeon.traces[self.floor] = Rc::new(self.trace.clone());
+        }
+    }
+
+    /**
+     * Puts the current floor back into the dungeon and shows another one.
+     */
+    pub fn change_floor(&mut self, floor: usize) {
+        if floor >= self.dungeon.levels.len() || floor == self.floor {
+            return;
+        }
+
+        self.store_floor();
+        self.show_floor(floor);
+        self.size = self.map.size;
+        self.exploration = None;
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            map: self.map.clone(),
+            seed: self.seed,
+            trace: self.trace.clone(),
+            dungeon: self.dungeon.clone(),
+            floor: self.floor,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.size = snapshot.map.size;
+        self.map = snapshot.map;
+        self.seed = snapshot.seed;
+        self.trace = snapshot.trace;
+        self.dungeon = snapshot.dungeon;
+        self.floor = snapshot.floor;
+        self.chunks = ChunkedWorld::new(self.seed, self.params);
+        self.exploration = None;
+    }
+
+    /**
+     * Saves the current state to the history. Call before changing the map.
+     */
+    pub fn checkpoint(&mut self) {
+        let snapshot = self.snapshot();
+        self.history.push(snapshot);
+    }
+
+    pub fn undo(&mut self) {
+        let current = self.snapshot();
+        if let Some(previous) = self.history.undo(current) {
+            self.restore(previous);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        let current = self.snapshot();
+        if let Some(next) = self.history.redo(current) {
+            self.restore(next);
         }
     }
 }
 
+/// Number of stone sprites in blocks.png a block picks from.
+const BLOCK_VARIANTS: u64 = 3;
+
 pub struct Block {
     pub pos: Vec2,
     pub atlas_idx: i32,
 }
 
+impl Block {
+    /**
+     * Block at pos with one of the stone sprites, picked from the seed and
+     * pos so the same block always looks the same.
+     */
+    pub fn new(pos: Vec2, seed: u64) -> Block {
+        let h = hash(&[seed, pos.x as u64, pos.y as u64]);
+        Block {
+            pos,
+            atlas_idx: (h % BLOCK_VARIANTS) as i32,
+        }
+    }
+}
+
+/**
+ * A single atlas cell drawn at a map position with a tint.
+ */
+pub struct DecoratedTile {
+    pub pos: Vec2,
+    pub atlas_pos: Vec2,
+    pub color: Color,
+}
+
+impl From<&Block> for DecoratedTile {
+    fn from(block: &Block) -> DecoratedTile {
+        DecoratedTile {
+            pos: block.pos,
+            atlas_pos: vec2(block.atlas_idx as f32, 0.0),
+            color: LIGHT,
+        }
+    }
+}
+
+/**
+ * Whose eyes the map is shown through.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Perspective {
+    /// Secrets look like the walls they hide in.
+    Player,
+    /// Secrets are highlighted.
+    Designer,
+}
+
+impl Perspective {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Perspective::Player => "player",
+            Perspective::Designer => "designer",
+        }
+    }
+}
+
+/**
+ * How the map is drawn.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum MapStyle {
+    /// With the tileset.
+    Tileset,
+    /// As blocks from blocks.png.
+    Blocks,
+}
+
 pub struct Timer {
     target: f32,
     current: f32,
@@ -205,30 +583,210 @@ impl Updateable for Timeline<'_> {
     }
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Tile {
     Wall,
     Floor,
-    Door,
+    Door(DoorState),
     Dirt,
+    StairsUp,
+    StairsDown,
+}
+
+impl Tile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tile::Wall => "wall",
+            Tile::Floor => "floor",
+            Tile::Door(DoorState::Open) => "open door",
+            Tile::Door(DoorState::Closed) => "door",
+            Tile::Door(DoorState::Locked(_)) => "locked door",
+            Tile::Door(DoorState::Secret) => "secret door",
+            Tile::Dirt => "dirt",
+            Tile::StairsUp => "stairs up",
+            Tile::StairsDown => "stairs down",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DoorState {
+    Open,
+    Closed,
+    /// Opened by the key with the same id.
+    Locked(u8),
+    /// Looks like a wall until it's found.
+    Secret,
+}
+
+/**
+ * Which way a door runs. A horizontal door sits in a wall running left to
+ * right, so it's passed from above or below.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
 }
 
 #[derive(Clone, PartialEq, From)]
 pub struct AtlasTile(pub Vec2);
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpawnKind {
+    Monster,
+    Treasure,
+    Trap,
+    /// Opens the locked door with the same id.
+    Key(u8),
+}
+
+/**
+ * Decoration drawn on top of a floor tile. The number picks between sprite
+ * variants, or the part of a prop that spans several tiles.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PropKind {
+    Table(u8),
+    Chair(u8),
+    Bookshelf(u8),
+    Drawers,
+    Chest,
+    Rubble,
+    Torch,
+    /// Torch hanging on a wall, lighting the room next to it.
+    WallTorch,
+}
+
+impl PropKind {
+    pub const NAMES: [&'static str; 8] = [
+        "table",
+        "chair",
+        "bookshelf",
+        "drawers",
+        "chest",
+        "rubble",
+        "torch",
+        "wall_torch",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropKind::Table(_) => "table",
+            PropKind::Chair(_) => "chair",
+            PropKind::Bookshelf(_) => "bookshelf",
+            PropKind::Drawers => "drawers",
+            PropKind::Chest => "chest",
+            PropKind::Rubble => "rubble",
+            PropKind::Torch => "torch",
+            PropKind::WallTorch => "wall_torch",
+        }
+    }
+
+    /**
+     * The part or variant of the prop, 0 for props that only come in one.
+     */
+    pub fn variant(&self) -> u8 {
+        match self {
+            PropKind::Table(n) | PropKind::Chair(n) | PropKind::Bookshelf(n) => *n,
+            _ => 0,
+        }
+    }
+
+    /**
+     * The prop with the given name and part or variant, the reverse of name
+     * and variant.
+     */
+    pub fn from_name(name: &str, variant: u8) -> Option<PropKind> {
+        match name {
+            "table" => Some(PropKind::Table(variant)),
+            "chair" => Some(PropKind::Chair(variant)),
+            "bookshelf" => Some(PropKind::Bookshelf(variant)),
+            "drawers" => Some(PropKind::Drawers),
+            "chest" => Some(PropKind::Chest),
+            "rubble" => Some(PropKind::Rubble),
+            "torch" => Some(PropKind::Torch),
+            "wall_torch" => Some(PropKind::WallTorch),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Prop {
+    pub kind: PropKind,
+    pub pos: usize,
+}
+
+/**
+ * Entity or item standing on the tile index pos.
+ */
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Spawn {
+    pub kind: SpawnKind,
+    pub pos: usize,
+}
+
+#[derive(Clone)]
 pub struct Map {
     pub size: Vec2,
     pub tiles: Vec<Tile>,
     pub draw_tiles: Vec<AtlasTile>,
+    pub rooms: Vec<Room>,
+    pub start_room: Option<usize>,
+    pub goal_room: Option<usize>,
+    /// Rooms that have to be passed to get from the start to the goal room.
+    pub critical_path: Vec<usize>,
+    pub spawns: Vec<Spawn>,
+    pub props: Vec<Prop>,
+    /// Material of every tile, only used for walls.
+    pub materials: Vec<WallMaterial>,
+    /// Seed the map was generated from, picks the tile variants.
+    pub seed: u64,
+    /// Changes whenever the draw tiles are refreshed, so drawing code can
+    /// tell whether the map looks any different. Copies of a map share its
+    /// revision, but maps that look different never share one.
+    pub revision: u64,
 }
 
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
+
 impl Map {
+    pub fn new(size: Vec2) -> Map {
+        let len = (size.x * size.y) as usize;
+        Map {
+            size,
+            tiles: vec![Tile::Dirt; len],
+            draw_tiles: vec![AtlasTile::from(tileset().empty()); len],
+            rooms: Vec::new(),
+            start_room: None,
+            goal_room: None,
+            critical_path: Vec::new(),
+            spawns: Vec::new(),
+            props: Vec::new(),
+            materials: vec![WallMaterial::Stone; len],
+            seed: 0,
+            revision: REVISIONS.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * Gives the map a new revision. Call after changing how it looks.
+     */
+    pub fn touch(&mut self) {
+        self.revision = REVISIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn idx(&self, pos: Vec2) -> usize {
-        (pos.y * self.size.y + pos.x) as usize
+        (pos.y * self.size.x + pos.x) as usize
     }
 
     pub fn idx_xy(&self, x: usize, y: usize) -> usize {
-        y * self.size.y as usize + x
+        y * self.size.x as usize + x
+    }
+
+    pub fn in_bounds(&self, pos: Vec2) -> bool {
+        pos.x >= 0.0 && pos.y >= 0.0 && pos.x < self.size.x && pos.y < self.size.y
     }
 
     pub fn idx_to_vec2(&self, idx: usize) -> Vec2 {
@@ -238,24 +796,140 @@ impl Map {
         }
     }
 
+    pub fn spawn_at(&self, idx: usize) -> Option<&Spawn> {
+        self.spawns.iter().find(|s| s.pos == idx)
+    }
+
+    pub fn prop_at(&self, idx: usize) -> Option<&Prop> {
+        self.props.iter().find(|p| p.pos == idx)
+    }
+
     pub fn tile_at_pos(&self, pos: Vec2) -> Option<&Tile> {
-        let idx = self.idx(pos);
-        if pos.x < 0.0 || pos.y < 0.0 || idx >= self.tiles.len() {
+        if !self.in_bounds(pos) {
             return None;
         }
 
-        Some(&self.tiles[idx])
+        Some(&self.tiles[self.idx(pos)])
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum RoomKind {
+    Normal,
+    Start,
+    Goal,
+    Treasure,
+    Boss,
+    Library,
+    Prison,
+    Closet,
+    Vault,
+    /// Only reachable through a secret door.
+    Secret,
+}
+
+impl RoomKind {
+    pub const ALL: [RoomKind; 10] = [
+        RoomKind::Normal,
+        RoomKind::Start,
+        RoomKind::Goal,
+        RoomKind::Treasure,
+        RoomKind::Boss,
+        RoomKind::Library,
+        RoomKind::Prison,
+        RoomKind::Closet,
+        RoomKind::Vault,
+        RoomKind::Secret,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomKind::Normal => "normal",
+            RoomKind::Start => "start",
+            RoomKind::Goal => "goal",
+            RoomKind::Treasure => "treasure",
+            RoomKind::Boss => "boss",
+            RoomKind::Library => "library",
+            RoomKind::Prison => "prison",
+            RoomKind::Closet => "closet",
+            RoomKind::Vault => "vault",
+            RoomKind::Secret => "secret",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Room {
     pub pos: Vec2,
     pub size: Vec2,
+    pub kind: RoomKind,
+    /// Which cells of the bounding box belong to the room, row by row.
+    pub mask: Vec<bool>,
+    /// Cells just outside the room where doors have to go. None lets doors go
+    /// anywhere along the outline.
+    pub entrances: Option<Vec<Vec2>>,
 }
 
 impl Room {
-    pub fn new(pos: Vec2, size: Vec2) -> Room {
-        Room { pos, size }
+    pub fn new(pos: Vec2, size: Vec2, mask: Vec<bool>) -> Room {
+        Room {
+            pos,
+            size,
+            kind: RoomKind::Normal,
+            mask,
+            entrances: None,
+        }
+    }
+
+    /**
+     * Rectangle around the room grown by margin cells on every side.
+     */
+    pub fn padded(&self, margin: f32) -> Room {
+        let size = self.size + margin * 2.0;
+        let len = ((size.x + 1.0) * (size.y + 1.0)) as usize;
+        Room::new(self.pos - margin, size, vec![true; len])
+    }
+
+    /**
+     * Whether the cell at pos belongs to the room.
+     */
+    pub fn contains(&self, pos: Vec2) -> bool {
+        let local = pos - self.pos;
+        if local.x < 0.0 || local.y < 0.0 || local.x > self.size.x || local.y > self.size.y {
+            return false;
+        }
+
+        self.mask[(local.y * (self.size.x + 1.0) + local.x) as usize]
+    }
+
+    /**
+     * All floor cells of the room. Rooms are carved including their far edge,
+     * so a room covers size + 1 cells in each direction.
+     */
+    pub fn cells(&self) -> impl Iterator<Item = Vec2> + '_ {
+        (0..=self.size.x as i32)
+            .flat_map(move |x| {
+                (0..=self.size.y as i32).map(move |y| self.pos + vec2(x as f32, y as f32))
+            })
+            .filter(|c| self.contains(*c))
+    }
+
+    /**
+     * The cell of the room closest to the middle of its bounding box.
+     */
+    pub fn center(&self) -> Vec2 {
+        let middle = (self.pos + self.size / 2.0).floor();
+        self.cells()
+            .min_by(|a, b| {
+                a.distance_squared(middle)
+                    .total_cmp(&b.distance_squared(middle))
+            })
+            .unwrap_or(middle)
+    }
+
+    /// Number of floor tiles the room covers.
+    pub fn area(&self) -> usize {
+        self.mask.iter().filter(|m| **m).count()
     }
 }
 
@@ -284,16 +958,53 @@ impl Size for Room {
 }
 
 pub trait Rect {
-    fn intersects<T: Rect + Position + Size>(&self, other: &T) -> bool;
+    fn intersects<T: Rect + Position + Size + Mask>(&self, other: &T) -> bool;
+}
+
+pub trait Mask {
+    fn contains(&self, pos: Vec2) -> bool;
+}
+
+impl Mask for Room {
+    fn contains(&self, pos: Vec2) -> bool {
+        Room::contains(self, pos)
+    }
 }
 
 impl Rect for Room {
-    fn intersects<T: Rect + Position + Size>(&self, other: &T) -> bool {
+    /**
+     * Whether the two rooms share a cell. Bounding boxes are compared first so
+     * only rooms that are close get their masks checked.
+     */
+    fn intersects<T: Rect + Position + Size + Mask>(&self, other: &T) -> bool {
         let left = f32::max(self.pos.x, other.pos().x);
         let right = f32::min(self.pos.x + self.size.x, other.pos().x + other.width());
         let top = f32::max(self.pos.y, other.pos().y);
         let bottom = f32::min(self.pos.y + self.size.y, other.pos().y + other.height());
 
-        left < right && top < bottom
+        left <= right && top <= bottom && self.cells().any(|c| other.contains(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(zoom: f32, scaling: Scaling) -> CameraControl {
+        CameraControl {
+            target: Vec2::ZERO,
+            zoom,
+            scaling,
+        }
+    }
+
+    #[test]
+    fn integer_scaling_snaps_to_physical_pixels() {
+        // at 1.5 physical pixels per screen pixel a texel has to span 3 of them
+        assert_eq!(camera(0.6, Scaling::Integer).scale_at(1.5), 0.5);
+        assert_eq!(camera(0.5, Scaling::Integer).scale_at(2.0), 0.5);
+        assert_eq!(camera(0.3, Scaling::Integer).scale_at(1.0), 1.0 / 3.0);
+        assert_eq!(camera(2.2, Scaling::Integer).scale_at(1.0), 2.0);
+        assert_eq!(camera(0.6, Scaling::Free).scale_at(1.5), 0.6);
     }
 }
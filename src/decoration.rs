@@ -0,0 +1,305 @@
+use macroquad::{
+    prelude::{vec2, Vec2},
+    rand::{self, ChooseRandom},
+};
+
+use crate::{
+    components::{DoorState, Map, Prop, PropKind, Room, RoomKind, Tile, WallMaterial},
+    level_utils::{adjecent_idxs, is_walkable, is_wall, surrounding_idxs},
+    room_graph::{room_idxs, RoomGraph},
+};
+
+/// Cells between torches along a room wall.
+const TORCH_SPACING: i32 = 6;
+
+/**
+ * Returns the walkable tiles just outside the room that lead into it, which is
+ * every door or corridor opening.
+ */
+fn entrances(map: &Map, room_of: &[Option<usize>], room: usize) -> Vec<usize> {
+    let mut entrances = Vec::new();
+    for cell in map.rooms[room].cells().filter(|c| map.in_bounds(*c)) {
+        for adj in adjecent_idxs(map, map.idx(cell)) {
+            if room_of[adj] != Some(room)
+                && is_walkable_or_locked(&map.tiles[adj])
+                && !entrances.contains(&adj)
+            {
+                entrances.push(adj);
+            }
+        }
+    }
+
+    entrances
+}
+
+fn is_walkable_or_locked(tile: &Tile) -> bool {
+    matches!(tile, Tile::Door(DoorState::Locked(_))) || is_walkable(tile)
+}
+
+/**
+ * Gives every room without a kind yet a kind based on its size, its number of
+ * doors, whether it's a dead end and how many rooms away from the start it is.
+ * Rooms that already have a kind, like the start, the goal and vaults, keep it.
+ */
+pub fn classify_rooms(map: &mut Map) {
+    let start = match map.start_room {
+        Some(start) => start,
+        None => return,
+    };
+
+    let room_of = room_idxs(map);
+    let graph = RoomGraph::new(map);
+    let (depths, _) = graph.distances(start);
+    let max_depth = depths.iter().flatten().max().copied().unwrap_or(0);
+
+    // the boss waits in the room right before the way down
+    if map.critical_path.len() > 2 {
+        let boss = map.critical_path[map.critical_path.len() - 2];
+        if map.rooms[boss].kind == RoomKind::Normal && map.rooms[boss].area() >= 24 {
+            map.rooms[boss].kind = RoomKind::Boss;
+        }
+    }
+
+    for (i, depth) in depths.iter().enumerate() {
+        if map.rooms[i].kind != RoomKind::Normal {
+            continue;
+        }
+
+        let area = map.rooms[i].area();
+        let doors = entrances(map, &room_of, i).len();
+        let dead_end = graph.edges[i].len() <= 1;
+        map.rooms[i].kind = match depth {
+            _ if dead_end && area <= 16 => RoomKind::Closet,
+            Some(depth) if dead_end && depth * 2 >= max_depth => RoomKind::Treasure,
+            _ if area >= 40 && doors >= 3 && rand::gen_range(0, 2) == 0 => RoomKind::Library,
+            _ if area <= 36 && doors <= 3 && rand::gen_range(0, 3) == 0 => RoomKind::Prison,
+            _ => RoomKind::Normal,
+        };
+    }
+}
+
+/**
+ * Cells of the room where a prop can go: empty floor that isn't right in front
+ * of an entrance.
+ */
+fn free_cells(map: &Map, room: &Room, entrances: &[usize]) -> Vec<Vec2> {
+    room.cells()
+        .filter(|c| map.in_bounds(*c))
+        .filter(|c| {
+            let idx = map.idx(*c);
+            map.tiles[idx] == Tile::Floor
+                && map.spawn_at(idx).is_none()
+                && map.prop_at(idx).is_none()
+                && !adjecent_idxs(map, idx)
+                    .iter()
+                    .any(|a| entrances.contains(a))
+        })
+        .collect()
+}
+
+/**
+ * Places a prop at pos if the cell is still free.
+ */
+fn place(map: &mut Map, free: &mut Vec<Vec2>, pos: Vec2, kind: PropKind) {
+    if let Some(i) = free.iter().position(|c| *c == pos) {
+        free.remove(i);
+        let pos = map.idx(pos);
+        map.props.push(Prop { kind, pos });
+    }
+}
+
+fn corners(room: &Room) -> [Vec2; 4] {
+    [
+        room.pos,
+        room.pos + vec2(room.size.x, 0.0),
+        room.pos + vec2(0.0, room.size.y),
+        room.pos + room.size,
+    ]
+}
+
+/**
+ * Turns every other cell inside the room into a wall pillar. Only cells whose
+ * eight neighbours are all walkable are used, so pillars stand alone and never
+ * cut off a part of the room.
+ */
+fn place_pillars(map: &mut Map, room: &Room, free: &mut Vec<Vec2>) {
+    for x in (1..room.size.x as i32).step_by(2) {
+        for y in (1..room.size.y as i32).step_by(2) {
+            let pos = room.pos + vec2(x as f32, y as f32);
+            let idx = map.idx(pos);
+            let enclosed = surrounding_idxs(map, idx)
+                .iter()
+                .all(|s| is_walkable(&map.tiles[*s]));
+            if enclosed && free.contains(&pos) {
+                map.tiles[idx] = Tile::Wall;
+                free.retain(|c| *c != pos);
+            }
+        }
+    }
+}
+
+/**
+ * Places a three tile table with a chair at each end in the middle of the
+ * room, if it fits.
+ */
+fn place_table(map: &mut Map, room: &Room, free: &mut Vec<Vec2>) {
+    let center = room.center();
+    let cells = (-2..=2)
+        .map(|x| center + vec2(x as f32, 0.0))
+        .collect::<Vec<Vec2>>();
+    if !cells.iter().all(|c| free.contains(c)) {
+        return;
+    }
+
+    place(map, free, cells[0], PropKind::Chair(1));
+    for (part, cell) in cells[1..4].iter().enumerate() {
+        place(map, free, *cell, PropKind::Table(part as u8));
+    }
+    place(map, free, cells[4], PropKind::Chair(0));
+}
+
+fn scatter(map: &mut Map, free: &mut Vec<Vec2>, amount: usize, kind: PropKind) {
+    free.shuffle();
+    for _ in 0..amount {
+        match free.pop() {
+            Some(pos) => {
+                let pos = map.idx(pos);
+                map.props.push(Prop { kind, pos });
+            }
+            None => break,
+        }
+    }
+}
+
+/**
+ * Places props in every room following the decoration rules of its kind.
+ * Props are decals that don't block movement, except for pillars which are
+ * walls.
+ */
+pub fn decorate_rooms(map: &mut Map) {
+    let room_of = room_idxs(map);
+    for i in 0..map.rooms.len() {
+        let room = map.rooms[i].clone();
+        let entrances = entrances(map, &room_of, i);
+        let mut free = free_cells(map, &room, &entrances);
+
+        match room.kind {
+            RoomKind::Start | RoomKind::Goal => {
+                for corner in corners(&room) {
+                    place(map, &mut free, corner, PropKind::Torch);
+                }
+            }
+            RoomKind::Boss => {
+                place_pillars(map, &room, &mut free);
+                for corner in corners(&room) {
+                    place(map, &mut free, corner, PropKind::Torch);
+                }
+            }
+            RoomKind::Treasure => {
+                for corner in corners(&room) {
+                    place(map, &mut free, corner, PropKind::Chest);
+                }
+            }
+            RoomKind::Secret => {
+                let corner = corners(&room)[rand::gen_range(0, 4)];
+                place(map, &mut free, corner, PropKind::Chest);
+            }
+            RoomKind::Library => {
+                for x in 0..=room.size.x as i32 {
+                    let pos = room.pos + vec2(x as f32, 0.0);
+                    place(map, &mut free, pos, PropKind::Bookshelf(x as u8));
+                }
+                place_table(map, &room, &mut free);
+            }
+            RoomKind::Prison => {
+                place(map, &mut free, room.pos, PropKind::Chair(0));
+                scatter(map, &mut free, 3, PropKind::Rubble);
+            }
+            RoomKind::Closet => {
+                for x in 0..2 {
+                    let pos = room.pos + vec2(x as f32, 0.0);
+                    place(map, &mut free, pos, PropKind::Drawers);
+                }
+                scatter(map, &mut free, 1, PropKind::Rubble);
+            }
+            RoomKind::Vault => (),
+            RoomKind::Normal => {
+                let amount = rand::gen_range(0, 2);
+                scatter(map, &mut free, amount, PropKind::Rubble);
+            }
+        }
+    }
+}
+
+/**
+ * Mounts torches on the walls around every room but secret ones, every
+ * TORCH_SPACING cells along the wall. Only walls right next to the room floor
+ * are used, never pillars, and no two torches hang next to each other.
+ */
+pub fn mount_torches(map: &mut Map) {
+    for room in map.rooms.clone().iter() {
+        if room.kind == RoomKind::Secret {
+            continue;
+        }
+
+        let cells = room
+            .cells()
+            .filter(|c| map.in_bounds(*c))
+            .map(|c| map.idx(c))
+            .collect::<Vec<usize>>();
+        for idx in cells {
+            for wall in adjecent_idxs(map, idx) {
+                let pos = map.idx_to_vec2(wall);
+                let spaced = (pos.x as i32 + pos.y as i32) % TORCH_SPACING == 0;
+                if !spaced
+                    || map.tiles[wall] != Tile::Wall
+                    || map.rooms.iter().any(|r| r.contains(pos))
+                    || map.prop_at(wall).is_some()
+                {
+                    continue;
+                }
+                let crowded = surrounding_idxs(map, wall)
+                    .iter()
+                    .any(|s| matches!(map.prop_at(*s), Some(p) if p.kind == PropKind::WallTorch));
+                if !crowded {
+                    map.props.push(Prop {
+                        kind: PropKind::WallTorch,
+                        pos: wall,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Builds the walls around every room and its pillars from a material picked
+ * by its kind. Vaults, boss rooms and libraries are brick, other rooms only
+ * sometimes. Walls shared by two rooms take the material of the later one,
+ * corridor walls stay stone. Secret doors are built like the wall around them,
+ * so they don't stand out.
+ */
+pub fn assign_materials(map: &mut Map) {
+    for room in map.rooms.clone().iter() {
+        let material = match room.kind {
+            // secret rooms keep the walls they're hidden behind
+            RoomKind::Secret => continue,
+            RoomKind::Vault | RoomKind::Boss | RoomKind::Library => WallMaterial::Brick,
+            _ if rand::gen_range(0, 4) == 0 => WallMaterial::Brick,
+            _ => WallMaterial::Stone,
+        };
+
+        let cells = room
+            .cells()
+            .filter(|c| map.in_bounds(*c))
+            .map(|c| map.idx(c))
+            .collect::<Vec<usize>>();
+        for idx in cells {
+            for s in surrounding_idxs(map, idx).into_iter().chain([idx]) {
+                if is_wall(&map.tiles[s]) {
+                    map.materials[s] = material;
+                }
+            }
+        }
+    }
+}
@@ -1,35 +1,56 @@
 use macroquad::{
+    camera::{set_camera, Camera2D},
     color_u8,
     prelude::{vec2, Color, Rect},
-    shapes::draw_line,
+    shapes::{draw_circle, draw_line, draw_rectangle, draw_rectangle_lines},
+    text::draw_text,
     texture::{draw_texture_ex, DrawTextureParams, Texture2D},
+    time::get_time,
+    window::{screen_height, screen_width},
 };
 
 use crate::{
-    components::{Block, Map, Tile, World},
-    rand, TILE_SIZE,
+    chunks::{ChunkedWorld, CHUNK_SIZE},
+    components::{
+        dpi_scale, Block, DecoratedTile, DoorState, Map, Mode, Perspective, PropKind, RoomKind,
+        SpawnKind, Tile, World,
+    },
+    editor::Tool,
+    fov::{field_of_view, line_of_sight, FOV_RADIUS},
+    input::mouse_cell,
+    lighting::shade,
+    pathfinding::{astar, dijkstra_map},
+    tileset::tileset,
+    TILE_SIZE,
 };
 
 pub const DARK: Color = color_u8!(49, 47, 40, 255);
 pub const LIGHT: Color = color_u8!(218, 216, 209, 255);
 pub const DIM: Color = color_u8!(218, 216, 209, 25);
+pub const RED: Color = color_u8!(196, 82, 62, 255);
+pub const GREEN: Color = color_u8!(122, 168, 140, 255);
+pub const YELLOW: Color = color_u8!(214, 160, 96, 255);
+pub const PURPLE: Color = color_u8!(150, 108, 168, 255);
+
+/// How long a message stays in the status line.
+const STATUS_SECONDS: f64 = 5.0;
 
 pub trait Drawable {
     fn draw(&self, texture: &Texture2D);
 }
 
-impl Drawable for Block {
+impl Drawable for DecoratedTile {
     fn draw(&self, texture: &Texture2D) {
         draw_texture_ex(
             *texture,
             self.pos.x * TILE_SIZE,
             self.pos.y * TILE_SIZE,
-            LIGHT,
+            self.color,
             DrawTextureParams {
                 dest_size: Some(vec2(TILE_SIZE, TILE_SIZE)),
                 source: Some(Rect::new(
-                    rand::gen_range(0, 3) as f32 * TILE_SIZE,
-                    0.0,
+                    self.atlas_pos.x * TILE_SIZE,
+                    self.atlas_pos.y * TILE_SIZE,
                     TILE_SIZE,
                     TILE_SIZE,
                 )),
@@ -39,36 +60,170 @@ impl Drawable for Block {
     }
 }
 
+impl Drawable for Block {
+    fn draw(&self, texture: &Texture2D) {
+        DecoratedTile::from(self).draw(texture)
+    }
+}
+
+/**
+ * The cell at idx as a blocks.png tile: walls are stone blocks, doors and
+ * stairs get their own sprites and everything else is left empty.
+ */
+pub fn block_tile(map: &Map, idx: usize) -> Option<DecoratedTile> {
+    let pos = map.idx_to_vec2(idx);
+    let (atlas_pos, color) = match map.tiles[idx] {
+        Tile::Wall | Tile::Door(DoorState::Secret) => {
+            return Some((&Block::new(pos, map.seed)).into())
+        }
+        Tile::Door(DoorState::Open | DoorState::Closed) => (vec2(1.0, 1.0), RED),
+        Tile::Door(DoorState::Locked(id)) => (vec2(1.0, 1.0), key_color(id)),
+        Tile::StairsUp => (vec2(0.0, 1.0), LIGHT),
+        Tile::StairsDown => (vec2(0.0, 1.0), YELLOW),
+        Tile::Floor | Tile::Dirt => return None,
+    };
+    Some(DecoratedTile {
+        pos,
+        atlas_pos,
+        color,
+    })
+}
+
+/**
+ * Draws the map with blocks.png, only the part in view.
+ */
+pub fn draw_blocks(world: &World, texture: &Texture2D) {
+    let map = world.shown_map();
+    let (x0, y0, x1, y1) = cells_in(map, view_cells(world));
+    for y in y0..y1 {
+        for x in x0..x1 {
+            if let Some(tile) = block_tile(map, map.idx_xy(x, y)) {
+                tile.draw(texture);
+            }
+        }
+    }
+}
+
 impl Drawable for Map {
     fn draw(&self, texture: &Texture2D) {
-        self.draw_tiles.iter().enumerate().for_each(|(idx, tex)| {
-            let pos = self.idx_to_vec2(idx);
-            let atlas_pos = tex.0;
-            let foreground = match self.tiles[idx] {
+        let area = Rect::new(0.0, 0.0, self.size.x, self.size.y);
+        draw_map_region(self, texture, area, None);
+    }
+}
+
+/**
+ * Draws the cells of the map within area, given in cells, along with their
+ * props and spawns. With a light map every cell is shaded by how lit it is.
+ */
+pub fn draw_map_region(map: &Map, texture: &Texture2D, area: Rect, light: Option<&[f32]>) {
+    let in_area = |idx: usize| area.contains(map.idx_to_vec2(idx));
+    let lit = |idx: usize, color: Color| match light {
+        Some(light) => shade(color, light[idx]),
+        None => color,
+    };
+    let (x0, y0, x1, y1) = cells_in(map, area);
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let idx = map.idx_xy(x, y);
+            let pos = vec2(x as f32, y as f32);
+            let atlas_pos = map.draw_tiles[idx].0;
+            let foreground = match map.tiles[idx] {
                 Tile::Dirt => DARK,
+                Tile::Door(DoorState::Locked(id)) => key_color(id),
                 _ => LIGHT,
             };
 
-            // if let Some(atlas_pos) = atlas_pos {
             draw_texture_ex(
                 *texture,
                 pos.x * TILE_SIZE,
                 pos.y * TILE_SIZE,
-                foreground,
+                lit(idx, foreground),
                 DrawTextureParams {
                     dest_size: Some(vec2(TILE_SIZE, TILE_SIZE)),
-                    source: Some(Rect::new(
-                        atlas_pos.x * TILE_SIZE,
-                        atlas_pos.y * TILE_SIZE,
-                        TILE_SIZE,
-                        TILE_SIZE,
-                    )),
+                    source: Some(tileset().source(atlas_pos)),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    map.props
+        .iter()
+        .filter(|prop| match prop.kind {
+            PropKind::WallTorch => map.tiles[prop.pos] == Tile::Wall,
+            _ => map.tiles[prop.pos] == Tile::Floor,
+        })
+        .filter(|prop| in_area(prop.pos))
+        .for_each(|prop| {
+            let pos = map.idx_to_vec2(prop.pos);
+            let atlas_pos = tileset().prop_pos(&prop.kind);
+            draw_texture_ex(
+                *texture,
+                pos.x * TILE_SIZE,
+                pos.y * TILE_SIZE,
+                lit(prop.pos, LIGHT),
+                DrawTextureParams {
+                    dest_size: Some(vec2(TILE_SIZE, TILE_SIZE)),
+                    source: Some(tileset().source(atlas_pos)),
                     ..Default::default()
                 },
             );
-            // }
         });
+
+    map.spawns
+        .iter()
+        .filter(|spawn| in_area(spawn.pos))
+        .for_each(|spawn| {
+            let pos = map.idx_to_vec2(spawn.pos);
+            let atlas_pos = tileset().spawn_pos(&spawn.kind);
+            let foreground = match spawn.kind {
+                SpawnKind::Key(id) => key_color(id),
+                _ => LIGHT,
+            };
+            draw_texture_ex(
+                *texture,
+                pos.x * TILE_SIZE,
+                pos.y * TILE_SIZE,
+                lit(spawn.pos, foreground),
+                DrawTextureParams {
+                    dest_size: Some(vec2(TILE_SIZE, TILE_SIZE)),
+                    source: Some(tileset().source(atlas_pos)),
+                    ..Default::default()
+                },
+            );
+        });
+}
+
+/**
+ * Draws the loaded chunks in view, each moved to its place in the world.
+ * Chunks that aren't loaded yet are left empty until streaming catches up.
+ */
+pub fn draw_chunks(world: &World, texture: &Texture2D) {
+    let view = world.camera.viewport();
+    let (min, max) = ChunkedWorld::chunks_in(view);
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            if let Some(chunk) = world.chunks.chunks.get(&(x, y)) {
+                let offset = vec2(x as f32, y as f32) * CHUNK_SIZE as f32 * TILE_SIZE;
+                set_camera(&Camera2D::from_display_rect(Rect::new(
+                    view.x - offset.x,
+                    view.y - offset.y,
+                    view.w,
+                    view.h,
+                )));
+                chunk.draw(texture);
+            }
+        }
     }
+    set_camera(&world.camera.camera2d());
+}
+
+/**
+ * Tint shared by a key and its locked door.
+ */
+pub fn key_color(id: u8) -> Color {
+    [YELLOW, GREEN, RED, LIGHT][id as usize % 4]
 }
 
 pub fn draw_grid(world: &World) {
@@ -93,3 +248,416 @@ pub fn draw_grid(world: &World) {
         );
     }
 }
+
+/**
+ * Outlines the cells the editor is about to paint.
+ */
+pub fn draw_editor(world: &World) {
+    if world.mode != Mode::Edit {
+        return;
+    }
+
+    let cell = mouse_cell(world);
+    let (min, max) = match world.editor.drag_start {
+        Some(start) => (start.min(cell), start.max(cell)),
+        None => (cell, cell),
+    };
+    draw_rectangle_lines(
+        min.x * TILE_SIZE,
+        min.y * TILE_SIZE,
+        (max.x - min.x + 1.0) * TILE_SIZE,
+        (max.y - min.y + 1.0) * TILE_SIZE,
+        2.0,
+        LIGHT,
+    );
+}
+
+/**
+ * Draws the distance heatmap from the picked start cell and the path to the
+ * picked goal cell.
+ */
+pub fn draw_path_view(world: &World) {
+    if world.mode != Mode::Path {
+        return;
+    }
+
+    let map = &world.map;
+    let from = match world.path_tool.from {
+        Some(from) if map.in_bounds(from) => map.idx(from),
+        _ => return,
+    };
+
+    let distances = dijkstra_map(map, &[from], &world.path_costs);
+    let max = distances
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or(0)
+        .max(1) as f32;
+    distances.iter().enumerate().for_each(|(idx, d)| {
+        if let Some(d) = d {
+            let t = *d as f32 / max;
+            fill_cell(map, idx, Color::new(t, 1.0 - t, 0.3, 0.4));
+        }
+    });
+    outline_cell(map, from, GREEN);
+
+    let to = match world.path_tool.to {
+        Some(to) if map.in_bounds(to) => map.idx(to),
+        _ => return,
+    };
+    outline_cell(map, to, RED);
+    if let Some(path) = astar(map, from, to, &world.path_costs) {
+        path.windows(2).for_each(|step| {
+            let a = (map.idx_to_vec2(step[0]) + 0.5) * TILE_SIZE;
+            let b = (map.idx_to_vec2(step[1]) + 0.5) * TILE_SIZE;
+            draw_line(a.x, a.y, b.x, b.y, 3.0, YELLOW);
+        });
+    }
+}
+
+/**
+ * Dims every tile that can't be seen from the cell under the mouse and draws
+ * the line of sight to the picked target cell.
+ */
+pub fn draw_fov_view(world: &World) {
+    if world.mode != Mode::Fov {
+        return;
+    }
+
+    let map = &world.map;
+    let eye = mouse_cell(world);
+    let visible = field_of_view(map, eye, FOV_RADIUS);
+    visible.iter().enumerate().for_each(|(idx, v)| {
+        if !v {
+            fill_cell(map, idx, Color { a: 0.8, ..DARK });
+        }
+    });
+
+    if let Some(target) = world.sight_target {
+        let color = if line_of_sight(map, eye, target) {
+            GREEN
+        } else {
+            RED
+        };
+        let a = (eye + 0.5) * TILE_SIZE;
+        let b = (target + 0.5) * TILE_SIZE;
+        draw_line(a.x, a.y, b.x, b.y, 2.0, color);
+    }
+}
+
+/**
+ * Hides the cells the player hasn't seen yet and dims the ones they saw before
+ * but can't see right now.
+ */
+pub fn draw_play_view(world: &World) {
+    if world.mode != Mode::Play {
+        return;
+    }
+    let exploration = match &world.exploration {
+        Some(exploration) => exploration,
+        None => return,
+    };
+
+    let map = &world.map;
+    let (x0, y0, x1, y1) = cells_in(map, view_cells(world));
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let idx = map.idx_xy(x, y);
+            if !exploration.seen[idx] {
+                fill_cell(map, idx, DARK);
+            } else if !exploration.visible[idx] {
+                fill_cell(map, idx, Color { a: 0.6, ..DARK });
+            }
+        }
+    }
+
+    let player = (exploration.player + 0.5) * TILE_SIZE;
+    draw_circle(player.x, player.y, TILE_SIZE / 3.0, YELLOW);
+}
+
+/**
+ * Draws the current mode in screen space. Expects the default camera to be set.
+ */
+pub fn draw_hud(world: &World) {
+    let details = match world.mode {
+        Mode::Edit => {
+            let tool = match world.editor.tool {
+                Tool::Brush => "brush",
+                Tool::Rectangle => "rectangle",
+                Tool::Fill => "fill",
+            };
+            format!("{} {}", tool, world.editor.tile.name())
+        }
+        Mode::Path => "left click start, right click goal".to_owned(),
+        Mode::Fov => "visible from mouse, click to check line of sight".to_owned(),
+        Mode::Chunks => {
+            let chunk = (world.camera.target / (CHUNK_SIZE as f32 * TILE_SIZE)).floor();
+            format!(
+                "{} chunks loaded, chunk {},{}",
+                world.chunks.chunks.len(),
+                chunk.x,
+                chunk.y
+            )
+        }
+        Mode::Play => match &world.exploration {
+            Some(exploration) => format!(
+                "arrow keys move, {}% explored",
+                exploration.explored * 100 / exploration.walkable.max(1)
+            ),
+            None => "nowhere to start".to_owned(),
+        },
+        Mode::View => String::new(),
+    };
+    draw_text(
+        &format!(
+            "floor {}/{} {} {} {}",
+            world.floor + 1,
+            world.dungeon.levels.len().max(1),
+            world.mode.name(),
+            world.perspective.name(),
+            details
+        ),
+        8.0,
+        20.0,
+        20.0,
+        LIGHT,
+    );
+    if let Some((message, at)) = &world.status {
+        if get_time() - at < STATUS_SECONDS {
+            draw_text(message, 8.0, 40.0, 20.0, LIGHT);
+        }
+    }
+}
+
+/**
+ * The cells in view plus one on every side, as a rect in cells.
+ */
+fn view_cells(world: &World) -> Rect {
+    let view = world.camera.viewport();
+    Rect::new(
+        (view.x / TILE_SIZE).floor() - 1.0,
+        (view.y / TILE_SIZE).floor() - 1.0,
+        (view.w / TILE_SIZE).ceil() + 2.0,
+        (view.h / TILE_SIZE).ceil() + 2.0,
+    )
+}
+
+/**
+ * First and last column and row, the last ones excluded, of the cells of the
+ * map within area.
+ */
+fn cells_in(map: &Map, area: Rect) -> (usize, usize, usize, usize) {
+    let (x0, y0) = (area.x.max(0.0) as usize, area.y.max(0.0) as usize);
+    let x1 = (area.right().min(map.size.x)).max(0.0) as usize;
+    let y1 = (area.bottom().min(map.size.y)).max(0.0) as usize;
+    (x0, y0, x1, y1)
+}
+
+fn fill_cell(map: &Map, idx: usize, color: Color) {
+    let pos = map.idx_to_vec2(idx);
+    draw_rectangle(
+        pos.x * TILE_SIZE,
+        pos.y * TILE_SIZE,
+        TILE_SIZE,
+        TILE_SIZE,
+        color,
+    );
+}
+
+fn outline_cell(map: &Map, idx: usize, color: Color) {
+    let pos = map.idx_to_vec2(idx);
+    draw_rectangle_lines(
+        pos.x * TILE_SIZE,
+        pos.y * TILE_SIZE,
+        TILE_SIZE,
+        TILE_SIZE,
+        2.0,
+        color,
+    );
+}
+
+/**
+ * Highlights secret rooms and doors in the designer perspective. The player
+ * perspective draws the map with them hidden instead, see World::shown_map.
+ */
+pub fn draw_secrets(world: &World) {
+    if world.perspective != Perspective::Designer {
+        return;
+    }
+
+    let map = &world.map;
+    map.rooms
+        .iter()
+        .filter(|room| room.kind == RoomKind::Secret)
+        .flat_map(|room| room.cells())
+        .filter(|cell| map.in_bounds(*cell))
+        .for_each(|cell| fill_cell(map, map.idx(cell), Color { a: 0.25, ..PURPLE }));
+    map.tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| **tile == Tile::Door(DoorState::Secret))
+        .for_each(|(idx, _)| {
+            fill_cell(map, idx, Color { a: 0.6, ..PURPLE });
+            outline_cell(map, idx, PURPLE);
+        });
+}
+
+/**
+ * Draws the enabled debug layers from the last generation run.
+ */
+pub fn draw_overlays(world: &World) {
+    let map = &world.map;
+    let trace = &world.trace;
+    let overlays = &world.overlays;
+
+    if overlays.pruned_corridors {
+        trace
+            .pruned_corridors
+            .iter()
+            .flatten()
+            .for_each(|idx| fill_cell(map, *idx, Color { a: 0.5, ..RED }));
+    }
+
+    if overlays.dfs_order {
+        let len = trace.dfs_order.len().max(1) as f32;
+        trace.dfs_order.iter().enumerate().for_each(|(i, idx)| {
+            let t = i as f32 / len;
+            fill_cell(map, *idx, Color::new(t, 0.2, 1.0 - t, 0.6));
+        });
+    }
+
+    if overlays.corridor_starts {
+        trace.corridor_starts.iter().for_each(|idx| {
+            let pos = map.idx_to_vec2(*idx);
+            draw_circle(
+                (pos.x + 0.5) * TILE_SIZE,
+                (pos.y + 0.5) * TILE_SIZE,
+                2.0,
+                YELLOW,
+            );
+        });
+    }
+
+    if overlays.doors {
+        trace
+            .door_candidates
+            .iter()
+            .for_each(|idx| outline_cell(map, *idx, YELLOW));
+        trace
+            .doors
+            .iter()
+            .for_each(|idx| fill_cell(map, *idx, Color { a: 0.6, ..GREEN }));
+    }
+
+    // rooms on the critical path are yellow, optional side rooms red
+    if overlays.rooms {
+        map.rooms.iter().enumerate().for_each(|(i, room)| {
+            let color = if map.critical_path.contains(&i) {
+                YELLOW
+            } else {
+                RED
+            };
+            let label = match room.kind {
+                RoomKind::Normal => i.to_string(),
+                kind => format!("{} {}", i, kind.name()),
+            };
+            draw_rectangle_lines(
+                room.pos.x * TILE_SIZE,
+                room.pos.y * TILE_SIZE,
+                (room.size.x + 1.0) * TILE_SIZE,
+                (room.size.y + 1.0) * TILE_SIZE,
+                2.0,
+                color,
+            );
+            draw_text(
+                &label,
+                room.pos.x * TILE_SIZE + 4.0,
+                room.pos.y * TILE_SIZE + 14.0,
+                16.0,
+                color,
+            );
+        });
+    }
+}
+
+const MINIMAP_MARGIN: f32 = 8.0;
+
+/**
+ * Screen pixels per mini-map tile: a whole number of physical pixels, so the
+ * tiles stay sharp and evenly sized on high-DPI screens.
+ */
+pub fn minimap_cell() -> f32 {
+    let dpi = dpi_scale();
+    dpi.round().max(1.0) / dpi
+}
+
+/**
+ * Screen space area of the mini-map in the bottom right corner.
+ */
+pub fn minimap_rect(map: &Map) -> Rect {
+    let size = map.size * minimap_cell();
+    Rect::new(
+        screen_width() - size.x - MINIMAP_MARGIN,
+        screen_height() - size.y - MINIMAP_MARGIN,
+        size.x,
+        size.y,
+    )
+}
+
+/**
+ * Draws the whole map at one mini-map cell per tile along with the camera
+ * viewport.
+ * Expects the default camera to be set.
+ */
+pub fn draw_minimap(world: &World) {
+    if matches!(world.mode, Mode::Chunks | Mode::Play) {
+        return;
+    }
+    let map = world.shown_map();
+    let area = minimap_rect(map);
+    let cell = minimap_cell();
+
+    draw_rectangle(area.x - 1.0, area.y - 1.0, area.w + 2.0, area.h + 2.0, DARK);
+    map.tiles.iter().enumerate().for_each(|(idx, tile)| {
+        let color = match tile {
+            Tile::Door(DoorState::Secret) => PURPLE,
+            Tile::Wall => GREEN,
+            Tile::Floor => LIGHT,
+            Tile::Door(DoorState::Open | DoorState::Closed) => RED,
+            Tile::Door(DoorState::Locked(id)) => key_color(*id),
+            Tile::StairsUp | Tile::StairsDown => YELLOW,
+            Tile::Dirt => return,
+        };
+        let pos = map.idx_to_vec2(idx) * cell;
+        draw_rectangle(area.x + pos.x, area.y + pos.y, cell, cell, color);
+    });
+    draw_rectangle_lines(
+        area.x - 1.0,
+        area.y - 1.0,
+        area.w + 2.0,
+        area.h + 2.0,
+        1.0,
+        DIM,
+    );
+
+    let viewport = world.camera.viewport();
+    let view = Rect::new(
+        viewport.x / TILE_SIZE * cell,
+        viewport.y / TILE_SIZE * cell,
+        viewport.w / TILE_SIZE * cell,
+        viewport.h / TILE_SIZE * cell,
+    )
+    .intersect(Rect::new(0.0, 0.0, area.w, area.h));
+    if let Some(view) = view {
+        draw_rectangle_lines(
+            area.x + view.x,
+            area.y + view.y,
+            view.w,
+            view.h,
+            1.0,
+            YELLOW,
+        );
+    }
+}
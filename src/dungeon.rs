@@ -0,0 +1,47 @@
+use std::rc::Rc;
+
+use macroquad::prelude::Vec2;
+
+use crate::{
+    components::{Map, Tile},
+    level::{generate_dungeon, GenerationTrace, GeneratorParams},
+    level_utils::find_tile,
+};
+
+/**
+ * Stack of floors generated together. The down stairs of every floor sit at
+ * the same position as the up stairs of the floor below. Floors are shared
+ * between copies, so copying the dungeon for the history is cheap.
+ */
+#[derive(Clone, Default)]
+pub struct MultiLevelDungeon {
+    pub levels: Vec<Rc<Map>>,
+    pub traces: Vec<Rc<GenerationTrace>>,
+}
+
+impl MultiLevelDungeon {
+    /**
+     * Generates params.floors floors of the given size. Every floor gets its
+     * own seed derived from seed, so the first floor matches a single floor
+     * generated with the same seed, and its params scaled by its depth.
+     */
+    pub fn generate(size: Vec2, seed: u64, params: &GeneratorParams) -> MultiLevelDungeon {
+        let mut dungeon = MultiLevelDungeon::default();
+        let mut entry = None;
+        for depth in 0..params.floors.max(1) {
+            let mut map = Map::new(size);
+            let trace = generate_dungeon(
+                &mut map,
+                seed.wrapping_add(depth as u64),
+                &params.for_depth(depth),
+                entry,
+            );
+            entry = find_tile(&map, Tile::StairsDown).map(|idx| map.idx_to_vec2(idx));
+
+            dungeon.levels.push(Rc::new(map));
+            dungeon.traces.push(Rc::new(trace));
+        }
+
+        dungeon
+    }
+}
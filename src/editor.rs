@@ -0,0 +1,175 @@
+use macroquad::prelude::*;
+
+use crate::{
+    components::{Map, Tile},
+    history::Snapshot,
+    level_utils::{adjecent_idxs, update_draw_tiles},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Tool {
+    Brush,
+    Rectangle,
+    Fill,
+}
+
+pub struct Editor {
+    pub tool: Tool,
+    pub tile: Tile,
+    pub drag_start: Option<Vec2>,
+    pub stroke: Option<Snapshot>,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor {
+            tool: Tool::Brush,
+            tile: Tile::Floor,
+            drag_start: None,
+            stroke: None,
+        }
+    }
+}
+
+/**
+ * Sets the tile at pos and returns the changed index, if any.
+ */
+pub fn paint(map: &mut Map, pos: Vec2, tile: Tile) -> Vec<usize> {
+    if !map.in_bounds(pos) {
+        return Vec::new();
+    }
+
+    let idx = map.idx(pos);
+    if map.tiles[idx] == tile {
+        return Vec::new();
+    }
+
+    map.tiles[idx] = tile;
+    update_draw_tiles(map, &[idx]);
+    vec![idx]
+}
+
+/**
+ * Fills the rectangle spanned by the two corners a and b (inclusive) with tile
+ * and returns the changed indices.
+ */
+pub fn paint_rect(map: &mut Map, a: Vec2, b: Vec2, tile: Tile) -> Vec<usize> {
+    let min = a.min(b).max(Vec2::ZERO);
+    let max = a.max(b).min(map.size - Vec2::ONE);
+    // the whole rectangle lies off the map
+    if min.x > max.x || min.y > max.y {
+        return Vec::new();
+    }
+
+    let mut changed = Vec::new();
+    for x in min.x as usize..=max.x as usize {
+        for y in min.y as usize..=max.y as usize {
+            let idx = map.idx_xy(x, y);
+            if map.tiles[idx] != tile {
+                map.tiles[idx] = tile;
+                changed.push(idx);
+            }
+        }
+    }
+
+    update_draw_tiles(map, &changed);
+    changed
+}
+
+/**
+ * Replaces the orthogonally connected area of equal tiles starting at pos with
+ * tile and returns the changed indices.
+ */
+pub fn flood_fill(map: &mut Map, pos: Vec2, tile: Tile) -> Vec<usize> {
+    if !map.in_bounds(pos) {
+        return Vec::new();
+    }
+
+    let start = map.idx(pos);
+    let target = map.tiles[start];
+    if target == tile {
+        return Vec::new();
+    }
+
+    let mut changed = Vec::new();
+    let mut stack = vec![start];
+    map.tiles[start] = tile;
+    while let Some(idx) = stack.pop() {
+        changed.push(idx);
+        for adj in adjecent_idxs(map, idx) {
+            if map.tiles[adj] == target {
+                map.tiles[adj] = tile;
+                stack.push(adj);
+            }
+        }
+    }
+
+    update_draw_tiles(map, &changed);
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paint_rect_fills_both_corners() {
+        let mut map = Map::new(vec2(8.0, 8.0));
+        let changed = paint_rect(&mut map, vec2(3.0, 2.0), vec2(1.0, 1.0), Tile::Floor);
+        assert_eq!(changed.len(), 6);
+        assert!(map.tiles[map.idx_xy(1, 1)] == Tile::Floor);
+        assert!(map.tiles[map.idx_xy(3, 2)] == Tile::Floor);
+        assert!(map.tiles[map.idx_xy(4, 2)] == Tile::Dirt);
+    }
+
+    #[test]
+    fn paint_rect_clips_to_the_map() {
+        let mut map = Map::new(vec2(8.0, 8.0));
+        let changed = paint_rect(&mut map, vec2(-2.0, 6.0), vec2(1.0, 10.0), Tile::Wall);
+        assert_eq!(changed.len(), 4);
+        assert!(changed.iter().all(|idx| map.tiles[*idx] == Tile::Wall));
+    }
+
+    #[test]
+    fn paint_rect_off_the_map_paints_nothing() {
+        let mut map = Map::new(vec2(8.0, 8.0));
+        let corners = [
+            (vec2(-5.0, -5.0), vec2(-2.0, -2.0)),
+            (vec2(9.0, 2.0), vec2(12.0, 4.0)),
+            (vec2(2.0, -3.0), vec2(4.0, -1.0)),
+        ];
+        for (a, b) in corners {
+            assert!(paint_rect(&mut map, a, b, Tile::Wall).is_empty());
+        }
+        assert!(map.tiles.iter().all(|t| *t == Tile::Dirt));
+    }
+
+    #[test]
+    fn flood_fill_stays_within_its_area() {
+        let mut map = Map::new(vec2(8.0, 8.0));
+        // a wall splitting the map into a left and a right half
+        paint_rect(&mut map, vec2(4.0, 0.0), vec2(4.0, 7.0), Tile::Wall);
+        let changed = flood_fill(&mut map, vec2(1.0, 1.0), Tile::Floor);
+        assert_eq!(changed.len(), 32);
+        assert!(map.tiles[map.idx_xy(0, 7)] == Tile::Floor);
+        assert!(map.tiles[map.idx_xy(4, 3)] == Tile::Wall);
+        assert!(map.tiles[map.idx_xy(5, 3)] == Tile::Dirt);
+    }
+
+    #[test]
+    fn flood_fill_does_not_cross_diagonals() {
+        let mut map = Map::new(vec2(3.0, 3.0));
+        let (a, b) = (map.idx_xy(1, 0), map.idx_xy(0, 1));
+        map.tiles[a] = Tile::Wall;
+        map.tiles[b] = Tile::Wall;
+        assert_eq!(flood_fill(&mut map, Vec2::ZERO, Tile::Floor).len(), 1);
+    }
+
+    #[test]
+    fn flood_fill_with_the_same_tile_or_off_the_map_does_nothing() {
+        let mut map = Map::new(vec2(8.0, 8.0));
+        assert!(flood_fill(&mut map, vec2(2.0, 2.0), Tile::Dirt).is_empty());
+        assert!(flood_fill(&mut map, vec2(-1.0, 2.0), Tile::Floor).is_empty());
+        assert!(map.tiles.iter().all(|t| *t == Tile::Dirt));
+    }
+}
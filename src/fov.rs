@@ -0,0 +1,148 @@
+use macroquad::prelude::Vec2;
+
+use crate::{components::Map, level_utils::is_opaque};
+
+/**
+ * Radius used by the field of view viewer mode.
+ */
+pub const FOV_RADIUS: i32 = 10;
+
+// Transforms mapping the first octant onto each of the eight octants.
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+fn opaque_at(map: &Map, x: i32, y: i32) -> bool {
+    let pos = Vec2::new(x as f32, y as f32);
+    !map.in_bounds(pos) || is_opaque(&map.tiles[map.idx(pos)])
+}
+
+/**
+ * Returns for every tile whether it can be seen from origin within radius,
+ * using recursive shadowcasting. Opaque tiles are visible themselves but hide
+ * whatever is behind them.
+ */
+pub fn field_of_view(map: &Map, origin: Vec2, radius: i32) -> Vec<bool> {
+    let mut visible = vec![false; map.tiles.len()];
+    if !map.in_bounds(origin) {
+        return visible;
+    }
+
+    visible[map.idx(origin)] = true;
+    for octant in OCTANTS.iter() {
+        cast_light(
+            map,
+            &mut visible,
+            (origin.x as i32, origin.y as i32),
+            radius,
+            1,
+            (1.0, 0.0),
+            *octant,
+        );
+    }
+
+    visible
+}
+
+fn cast_light(
+    map: &Map,
+    visible: &mut [bool],
+    origin: (i32, i32),
+    radius: i32,
+    row: i32,
+    slopes: (f32, f32),
+    octant: (i32, i32, i32, i32),
+) {
+    let (mut start, end) = slopes;
+    if start < end {
+        return;
+    }
+
+    let (xx, xy, yx, yy) = octant;
+    let mut new_start = 0.0;
+    for j in row..=radius {
+        let dy = -j;
+        let mut blocked = false;
+        for dx in -j..=0 {
+            let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+            if start < right_slope {
+                continue;
+            } else if end > left_slope {
+                break;
+            }
+
+            let x = origin.0 + dx * xx + dy * xy;
+            let y = origin.1 + dx * yx + dy * yy;
+            let pos = Vec2::new(x as f32, y as f32);
+            if map.in_bounds(pos) && dx * dx + dy * dy <= radius * radius {
+                visible[map.idx(pos)] = true;
+            }
+
+            if blocked {
+                if opaque_at(map, x, y) {
+                    new_start = right_slope;
+                } else {
+                    blocked = false;
+                    start = new_start;
+                }
+            } else if opaque_at(map, x, y) && j < radius {
+                blocked = true;
+                cast_light(
+                    map,
+                    visible,
+                    origin,
+                    radius,
+                    j + 1,
+                    (start, left_slope),
+                    octant,
+                );
+                new_start = right_slope;
+            }
+        }
+
+        if blocked {
+            break;
+        }
+    }
+}
+
+/**
+ * Returns whether there is a straight line from a to b that doesn't pass
+ * through an opaque tile. The end points themselves may be opaque.
+ */
+pub fn line_of_sight(map: &Map, a: Vec2, b: Vec2) -> bool {
+    let (mut x, mut y) = (a.x as i32, a.y as i32);
+    let (x1, y1) = (b.x as i32, b.y as i32);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 { 1 } else { -1 };
+    let sy = if y < y1 { 1 } else { -1 };
+    let mut err = dx + dy;
+
+    loop {
+        if x == x1 && y == y1 {
+            return true;
+        }
+        if (x, y) != (a.x as i32, a.y as i32) && opaque_at(map, x, y) {
+            return false;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
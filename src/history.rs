@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+
+use crate::{components::Map, dungeon::MultiLevelDungeon, level::GenerationTrace};
+
+/**
+ * Number of undo steps kept. Snapshots share the floors that weren't edited,
+ * so each one costs about one 64x64 floor, roughly 40kB plus its trace.
+ */
+pub const HISTORY_CAPACITY: usize = 50;
+
+/**
+ * State to go back to. Only the shown floor is copied into map, the floors in
+ * dungeon are shared with the world and other snapshots.
+ */
+#[derive(Clone)]
+pub struct Snapshot {
+    pub map: Map,
+    pub seed: u64,
+    pub trace: GenerationTrace,
+    pub dungeon: MultiLevelDungeon,
+    pub floor: usize,
+}
+
+pub struct History {
+    capacity: usize,
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /**
+     * Records the state from before a change. Dropping the oldest snapshot
+     * when the history is full and invalidating anything that could be redone.
+     */
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(snapshot);
+        self.redo.clear();
+    }
+
+    /**
+     * Returns the previous state, keeping current around for redo.
+     */
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let previous = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /**
+     * Returns the most recently undone state, keeping current around for undo.
+     */
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let next = self.redo.pop()?;
+        self.undo.push_back(current);
+        Some(next)
+    }
+}
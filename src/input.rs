@@ -1,30 +1,264 @@
 use macroquad::{
-    prelude::{is_key_down, is_key_released, KeyCode},
+    prelude::{
+        is_key_down, is_key_pressed, is_key_released, is_mouse_button_down,
+        is_mouse_button_pressed, is_mouse_button_released, mouse_position, vec2, KeyCode,
+        MouseButton, Vec2,
+    },
     time::get_frame_time,
+    ui::root_ui,
 };
 
-use crate::{components::World, level};
+use crate::{
+    components::{DoorState, MapStyle, Mode, Perspective, Scaling, Tile, World},
+    draw::{minimap_cell, minimap_rect},
+    editor::{flood_fill, paint, paint_rect, Tool},
+    level::{self, GenerationTrace},
+    play::Exploration,
+    save, TILE_SIZE,
+};
+
+/**
+ * Returns the map cell under the mouse cursor.
+ */
+pub fn mouse_cell(w: &World) -> Vec2 {
+    let mouse = w
+        .camera
+        .camera2d()
+        .screen_to_world(Vec2::from(mouse_position()));
+    (mouse / TILE_SIZE).floor()
+}
 
 pub fn input(w: &mut World) {
     let delta: f32 = 800.0;
 
-    if is_key_down(KeyCode::Down) {
-        w.camera.pos.y += delta * get_frame_time();
+    if w.mode == Mode::Play {
+        play_input(w);
+    } else if is_key_down(KeyCode::Down) {
+        w.camera.target.y += delta * get_frame_time();
     } else if is_key_down(KeyCode::Up) {
-        w.camera.pos.y -= delta * get_frame_time();
+        w.camera.target.y -= delta * get_frame_time();
     } else if is_key_down(KeyCode::Right) {
-        w.camera.pos.x += delta * get_frame_time();
+        w.camera.target.x += delta * get_frame_time();
     } else if is_key_down(KeyCode::Left) {
-        w.camera.pos.x -= delta * get_frame_time();
+        w.camera.target.x -= delta * get_frame_time();
     }
 
     if is_key_down(KeyCode::Q) {
-        w.camera.zoom -= 0.010;
+        w.camera.zoom = f32::max(w.camera.zoom - 0.010, 0.05);
     } else if is_key_down(KeyCode::E) {
         w.camera.zoom += 0.010;
     }
 
+    if is_key_pressed(KeyCode::I) {
+        w.camera.scaling = match w.camera.scaling {
+            Scaling::Free => Scaling::Integer,
+            Scaling::Integer => Scaling::Free,
+        };
+    }
+
     if is_key_released(KeyCode::R) {
-        let _ = level::generate_dungeon(&mut w.map);
+        w.regenerate(level::new_seed());
+    }
+
+    if is_key_pressed(KeyCode::P) {
+        w.panel.open = !w.panel.open;
+    }
+
+    if is_key_pressed(KeyCode::V) {
+        w.perspective = match w.perspective {
+            Perspective::Player => Perspective::Designer,
+            Perspective::Designer => Perspective::Player,
+        };
+    }
+
+    let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+    if ctrl && is_key_pressed(KeyCode::Z) {
+        w.undo();
+    } else if ctrl && is_key_pressed(KeyCode::Y) {
+        w.redo();
+    }
+
+    if is_key_pressed(KeyCode::PageDown) {
+        w.change_floor(w.floor + 1);
+    } else if is_key_pressed(KeyCode::PageUp) {
+        w.change_floor(w.floor.saturating_sub(1));
+    }
+
+    if is_key_pressed(KeyCode::Tab) {
+        w.mode = w.mode.next();
+        w.editor.drag_start = None;
+    }
+
+    let overlays = &mut w.overlays;
+    if is_key_pressed(KeyCode::F1) {
+        overlays.grid = !overlays.grid;
+    } else if is_key_pressed(KeyCode::F2) {
+        overlays.rooms = !overlays.rooms;
+    } else if is_key_pressed(KeyCode::F3) {
+        overlays.corridor_starts = !overlays.corridor_starts;
+    } else if is_key_pressed(KeyCode::F4) {
+        overlays.dfs_order = !overlays.dfs_order;
+    } else if is_key_pressed(KeyCode::F6) {
+        overlays.doors = !overlays.doors;
+    } else if is_key_pressed(KeyCode::F7) {
+        overlays.pruned_corridors = !overlays.pruned_corridors;
+    } else if is_key_pressed(KeyCode::F10) {
+        overlays.lighting = !overlays.lighting;
+    }
+
+    if is_key_pressed(KeyCode::F8) {
+        w.style = match w.style {
+            MapStyle::Tileset => MapStyle::Blocks,
+            MapStyle::Blocks => MapStyle::Tileset,
+        };
+    }
+    if is_key_pressed(KeyCode::F5) {
+        if let Err(e) = save::save_map(&w.map, save::SAVE_PATH) {
+            w.report(format!("Could not save map: {}", e));
+        }
+    }
+    if is_key_pressed(KeyCode::F9) {
+        match save::load_map(save::SAVE_PATH) {
+            Ok(map) => {
+                w.checkpoint();
+                w.size = map.size;
+                w.map = map;
+                w.trace = GenerationTrace::default();
+                w.store_floor();
+                w.exploration = None;
+            }
+            Err(e) => w.report(format!("Could not load map: {}", e)),
+        }
+    }
+
+    let mouse = Vec2::from(mouse_position());
+    let over_panel = w.panel.open && root_ui().is_mouse_over(mouse);
+    let over_minimap =
+        !matches!(w.mode, Mode::Chunks | Mode::Play) && minimap_rect(&w.map).contains(mouse);
+    if over_minimap && !over_panel {
+        minimap_input(w, mouse);
+    } else if !over_panel {
+        match w.mode {
+            Mode::Edit => editor_input(w),
+            Mode::Path => path_input(w),
+            Mode::Fov => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    w.sight_target = Some(mouse_cell(w));
+                }
+            }
+            Mode::View | Mode::Chunks | Mode::Play => {}
+        }
+    }
+}
+
+/**
+ * The arrow keys move the player one cell at a time and the camera follows.
+ */
+fn play_input(w: &mut World) {
+    if w.exploration.is_none() {
+        w.exploration = Exploration::start(&w.map);
+    }
+    let exploration = match &mut w.exploration {
+        Some(exploration) => exploration,
+        None => return,
+    };
+
+    for (key, dir) in [
+        (KeyCode::Up, vec2(0.0, -1.0)),
+        (KeyCode::Down, vec2(0.0, 1.0)),
+        (KeyCode::Left, vec2(-1.0, 0.0)),
+        (KeyCode::Right, vec2(1.0, 0.0)),
+    ] {
+        if is_key_pressed(key) {
+            exploration.step(&w.map, dir);
+        }
+    }
+    w.camera.center_on((exploration.player + 0.5) * TILE_SIZE);
+}
+
+/**
+ * Left click picks the start of the path, right click the goal.
+ */
+fn path_input(w: &mut World) {
+    if is_mouse_button_pressed(MouseButton::Left) {
+        w.path_tool.from = Some(mouse_cell(w));
+    } else if is_mouse_button_pressed(MouseButton::Right) {
+        w.path_tool.to = Some(mouse_cell(w));
+    }
+}
+
+/**
+ * Clicking or dragging on the mini-map centers the camera on that tile.
+ */
+fn minimap_input(w: &mut World, mouse: Vec2) {
+    if is_mouse_button_down(MouseButton::Left) {
+        let area = minimap_rect(&w.map);
+        let tile = (mouse - vec2(area.x, area.y)) / minimap_cell();
+        w.camera.center_on(tile * TILE_SIZE);
+    }
+}
+
+fn editor_input(w: &mut World) {
+    if is_key_pressed(KeyCode::Key1) {
+        w.editor.tile = Tile::Floor;
+    } else if is_key_pressed(KeyCode::Key2) {
+        w.editor.tile = Tile::Wall;
+    } else if is_key_pressed(KeyCode::Key3) {
+        w.editor.tile = Tile::Door(DoorState::Closed);
+    } else if is_key_pressed(KeyCode::Key4) {
+        w.editor.tile = Tile::Dirt;
+    } else if is_key_pressed(KeyCode::Key5) {
+        w.editor.tile = Tile::StairsUp;
+    } else if is_key_pressed(KeyCode::Key6) {
+        w.editor.tile = Tile::StairsDown;
+    } else if is_key_pressed(KeyCode::Key7) {
+        w.editor.tile = Tile::Door(DoorState::Open);
+    } else if is_key_pressed(KeyCode::Key8) {
+        w.editor.tile = Tile::Door(DoorState::Secret);
+    }
+
+    if is_key_pressed(KeyCode::B) {
+        w.editor.tool = Tool::Brush;
+    } else if is_key_pressed(KeyCode::T) {
+        w.editor.tool = Tool::Rectangle;
+    } else if is_key_pressed(KeyCode::F) {
+        w.editor.tool = Tool::Fill;
+    }
+
+    let cell = mouse_cell(w);
+    let tile = w.editor.tile;
+    match w.editor.tool {
+        Tool::Brush => {
+            // a whole stroke is undone at once, so remember the map from before it
+            if is_mouse_button_pressed(MouseButton::Left) {
+                w.editor.stroke = Some(w.snapshot());
+            }
+            if is_mouse_button_down(MouseButton::Left) && !paint(&mut w.map, cell, tile).is_empty()
+            {
+                if let Some(before) = w.editor.stroke.take() {
+                    w.history.push(before);
+                }
+            }
+        }
+        Tool::Rectangle => {
+            if is_mouse_button_pressed(MouseButton::Left) {
+                w.editor.drag_start = Some(cell);
+            } else if is_mouse_button_released(MouseButton::Left) {
+                if let Some(start) = w.editor.drag_start.take() {
+                    let before = w.snapshot();
+                    if !paint_rect(&mut w.map, start, cell, tile).is_empty() {
+                        w.history.push(before);
+                    }
+                }
+            }
+        }
+        Tool::Fill => {
+            if is_mouse_button_pressed(MouseButton::Left) {
+                let before = w.snapshot();
+                if !flood_fill(&mut w.map, cell, tile).is_empty() {
+                    w.history.push(before);
+                }
+            }
+        }
     }
 }
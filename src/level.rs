@@ -1,112 +1,539 @@
-use std::ops::Range;
+use std::collections::VecDeque;
 
 use macroquad::{prelude::*, rand::ChooseRandom, texture::Texture2D};
 
 use crate::{
-    components::{AtlasTile, Block, Map, Rect, Room, Size, Tile, ROOM_SIZES},
+    components::{
+        Block, DoorState, Map, Rect, Room, RoomKind, Size, Spawn, SpawnKind, Tile,
+        LARGE_ROOM_SIZES, MIXED_ROOM_SIZES, ROOM_SIZES, SMALL_ROOM_SIZES,
+    },
+    decoration::{assign_materials, classify_rooms, decorate_rooms, mount_torches},
     level_utils::{
-        adjecent_idxs, get_tile_at_pos, get_wall_atlas_pos, is_adjecent_to_room, is_floor, is_room,
-        neighbourless_idxs, surrounding_idxs,
+        adjecent_idxs, door_orientation, get_tile_at_pos, is_adjecent_to_room, is_floor, is_room,
+        is_walkable, neighbourless_idxs, refresh_draw_tiles, surrounding_idxs, wall_mask,
     },
-    GAME_HEIGHT, GAME_WIDTH,
+    locks::{is_solvable, place_locks},
+    room_graph::RoomGraph,
+    secrets::place_secret_rooms,
+    shapes::{random_prefab, shape_mask, RoomShape},
+    spawns::place_spawns,
+    vaults::{vaults, Vault},
 };
 
 const ROOM_GENERATION_ATTEMPTS: i32 = 50;
 const CORRIDOR_MAX_LENGTH: usize = 20;
+// free cells kept around vaults
+const VAULT_MARGIN: f32 = 2.0;
+const ENTRY_ROOM_SIZE: f32 = 4.0;
+// fresh layouts tried before giving up on vaults
+const LAYOUT_ATTEMPTS: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Generator {
+    Sparse,
+}
+
+impl Generator {
+    pub const ALL: [Generator; 1] = [Generator::Sparse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Generator::Sparse => "sparse",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RoomSizes {
+    Default,
+    Small,
+    Large,
+    Mixed,
+}
+
+impl RoomSizes {
+    pub const ALL: [RoomSizes; 4] = [
+        RoomSizes::Default,
+        RoomSizes::Small,
+        RoomSizes::Large,
+        RoomSizes::Mixed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomSizes::Default => "default",
+            RoomSizes::Small => "small",
+            RoomSizes::Large => "large",
+            RoomSizes::Mixed => "mixed",
+        }
+    }
+
+    pub fn sizes(&self) -> &'static [Vec2] {
+        match self {
+            RoomSizes::Default => &ROOM_SIZES,
+            RoomSizes::Small => &SMALL_ROOM_SIZES,
+            RoomSizes::Large => &LARGE_ROOM_SIZES,
+            RoomSizes::Mixed => &MIXED_ROOM_SIZES,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GeneratorParams {
+    pub generator: Generator,
+    pub min_rooms: usize,
+    pub max_rooms: usize,
+    pub room_sizes: RoomSizes,
+    pub room_shapes: RoomShapes,
+    pub vaults: bool,
+    pub corridor_max_length: usize,
+    pub door_probability: f32,
+    pub lock_count: usize,
+    pub secret_rooms: usize,
+    pub spawn_density: f32,
+    pub spawn_min_distance: u32,
+    pub floors: usize,
+}
+
+/**
+ * Which shapes the generated rooms can have.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum RoomShapes {
+    Rectangles,
+    Mixed,
+    Prefabs,
+}
+
+impl RoomShapes {
+    pub const ALL: [RoomShapes; 3] = [
+        RoomShapes::Rectangles,
+        RoomShapes::Mixed,
+        RoomShapes::Prefabs,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RoomShapes::Rectangles => "rectangles",
+            RoomShapes::Mixed => "mixed",
+            RoomShapes::Prefabs => "prefabs",
+        }
+    }
+
+    /**
+     * Picks the size and mask of the next room.
+     */
+    fn pick(&self, sizes: &[Vec2]) -> (Vec2, Vec<bool>) {
+        let shape = match self {
+            RoomShapes::Rectangles => RoomShape::Rectangle,
+            RoomShapes::Mixed => RoomShape::ALL[rand::gen_range(0, RoomShape::ALL.len())],
+            RoomShapes::Prefabs => RoomShape::Prefab,
+        };
+        if shape == RoomShape::Prefab {
+            return random_prefab();
+        }
+
+        let size = sizes[rand::gen_range(0, sizes.len())];
+        (size, shape_mask(shape, size))
+    }
+}
+
+impl Default for GeneratorParams {
+    fn default() -> GeneratorParams {
+        GeneratorParams {
+            generator: Generator::Sparse,
+            min_rooms: 8,
+            max_rooms: 12,
+            room_sizes: RoomSizes::Default,
+            room_shapes: RoomShapes::Rectangles,
+            vaults: true,
+            corridor_max_length: CORRIDOR_MAX_LENGTH,
+            door_probability: 0.5,
+            lock_count: 2,
+            secret_rooms: 1,
+            spawn_density: 0.05,
+            spawn_min_distance: 8,
+            floors: 3,
+        }
+    }
+}
+
+impl GeneratorParams {
+    /**
+     * Params for the floor depth floors below the first one. Deeper floors get
+     * more rooms, locks and spawns, and spawns closer to the stairs.
+     */
+    pub fn for_depth(&self, depth: usize) -> GeneratorParams {
+        GeneratorParams {
+            min_rooms: self.min_rooms + depth,
+            max_rooms: self.max_rooms + depth,
+            lock_count: (self.lock_count + depth / 2).min(8),
+            spawn_density: self.spawn_density * (1.0 + 0.25 * depth as f32),
+            spawn_min_distance: self.spawn_min_distance.saturating_sub(2 * depth as u32),
+            ..*self
+        }
+    }
+}
+
+/**
+ * Intermediate results of a generation run, kept around for the debug overlays.
+ */
+#[derive(Clone, Default)]
+pub struct GenerationTrace {
+    pub steps: Vec<(Vec2, Tile)>,
+    pub corridor_starts: Vec<usize>,
+    pub dfs_order: Vec<usize>,
+    pub door_candidates: Vec<usize>,
+    pub doors: Vec<usize>,
+    pub pruned_corridors: Vec<Vec<usize>>,
+}
+
+/**
+ * Generates a dungeon into map. The same seed, params and entry always produce
+ * the same dungeon. With an entry the up stairs are put right there, so the
+ * map can continue the floor above.
+ */
+pub fn generate_dungeon(
+    map: &mut Map,
+    seed: u64,
+    params: &GeneratorParams,
+    entry: Option<Vec2>,
+) -> GenerationTrace {
+    rand::srand(seed);
+    map.seed = seed;
+    match params.generator {
+        Generator::Sparse => dungeon_1(map, params, entry),
+    }
+}
+
+/**
+ * Returns a new seed based on the current time.
+ */
+pub fn new_seed() -> u64 {
+    (miniquad::date::now() * 1000.0) as u64
+}
+
+/**
+ * Whether the room overlaps any of the placed rooms. Vaults keep some distance
+ * to everything else, so they stay sealed apart from their entrances and
+ * there is room to dig corridors to them.
+ */
+fn overlaps(placed_rooms: &[Room], room: &Room) -> bool {
+    placed_rooms
+        .iter()
+        .any(|r| match (&r.entrances, &room.entrances) {
+            (None, None) => r.intersects(room),
+            (Some(_), _) => r.padded(VAULT_MARGIN).intersects(room),
+            (None, Some(_)) => room.padded(VAULT_MARGIN).intersects(r),
+        })
+}
+
+/// Spawns marked in vault layouts, by cell.
+type VaultSpawns = Vec<(Vec2, SpawnKind)>;
+
+/**
+ * Places the given number of copies of every vault within the given bounds,
+ * next to the already placed rooms. Returns all rooms and the spawns marked in
+ * the vault layouts, or None if a copy didn't fit anywhere.
+ */
+fn place_vaults(
+    vaults: &[Vault],
+    bounds: Vec2,
+    mut placed_rooms: Vec<Room>,
+) -> Option<(Vec<Room>, VaultSpawns)> {
+    let mut spawns = Vec::new();
+
+    for vault in vaults.iter() {
+        for _ in 0..vault.count {
+            let mut placed = false;
+            for _ in 0..ROOM_GENERATION_ATTEMPTS {
+                let pos = vec2(
+                    rand::gen_range(VAULT_MARGIN, bounds.x).floor(),
+                    rand::gen_range(VAULT_MARGIN, bounds.y).floor(),
+                );
+                let (room, vault_spawns) = vault.stamp(pos);
+                if pos.x + room.size.x + VAULT_MARGIN >= bounds.x
+                    || pos.y + room.size.y + VAULT_MARGIN >= bounds.y
+                    || overlaps(&placed_rooms, &room)
+                {
+                    continue;
+                }
+
+                placed_rooms.push(room);
+                spawns.extend(vault_spawns);
+                placed = true;
+                break;
+            }
+            if !placed {
+                return None;
+            }
+        }
+    }
+
+    Some((placed_rooms, spawns))
+}
+
+/**
+ * Finds the shortest corridor from a vault entrance to the nearest floor,
+ * leaving the vault straight away from its wall. The corridor only goes
+ * through empty tiles and keeps clear of every vault's walls, so it can't
+ * open up a vault anywhere else. Returns the tiles to dig, or None if no floor
+ * can be reached.
+ */
+fn connect_entrance(map: &Map, rooms: &[Room], vault: &Room, entrance: Vec2) -> Option<Vec<usize>> {
+    let dir = if entrance.y < vault.pos.y {
+        vec2(0.0, -1.0)
+    } else if entrance.y > vault.pos.y + vault.size.y {
+        vec2(0.0, 1.0)
+    } else if entrance.x < vault.pos.x {
+        vec2(-1.0, 0.0)
+    } else {
+        vec2(1.0, 0.0)
+    };
+    let first = entrance + dir;
+    if !map.in_bounds(first) || near_vault(rooms, first) || map.tiles[map.idx(first)] != Tile::Dirt
+    {
+        return None;
+    }
+
+    let mut came_from = vec![None; map.tiles.len()];
+    let start = map.idx(first);
+    came_from[start] = Some(map.idx(entrance));
+    let mut queue = VecDeque::from([start]);
+    while let Some(idx) = queue.pop_front() {
+        let adjecent = adjecent_idxs(map, idx);
+        if adjecent.iter().any(|a| map.tiles[*a] == Tile::Floor) {
+            let mut dug = vec![idx];
+            let mut current = idx;
+            while current != start {
+                current = came_from[current]?;
+                dug.push(current);
+            }
+            return Some(dug);
+        }
+
+        for adj in adjecent {
+            if came_from[adj].is_some()
+                || map.tiles[adj] != Tile::Dirt
+                || near_vault(rooms, map.idx_to_vec2(adj))
+            {
+                continue;
+            }
+            came_from[adj] = Some(idx);
+            queue.push_back(adj);
+        }
+    }
+
+    None
+}
+
+/**
+ * Whether pos is inside a vault or its walls.
+ */
+fn near_vault(rooms: &[Room], pos: Vec2) -> bool {
+    rooms
+        .iter()
+        .any(|r| r.entrances.is_some() && r.padded(1.0).contains(pos))
+}
+
+/**
+ * Digs the shortest corridor from the room to the nearest floor it can't reach
+ * yet, if it can't reach any other room. The corridor keeps clear of vaults
+ * and of everything already reachable, so it only touches the room where it
+ * leaves it. Returns the tiles to dig, ending next to the room, or None if
+ * nothing needs to or can be dug.
+ */
+fn connect_room(map: &Map, rooms: &[Room], room: &Room) -> Option<Vec<usize>> {
+    let room_idxs = room.cells().map(|c| map.idx(c)).collect::<Vec<usize>>();
+    let mut reachable = vec![false; map.tiles.len()];
+    let mut queue = room_idxs.iter().copied().collect::<VecDeque<usize>>();
+    queue.iter().for_each(|idx| reachable[*idx] = true);
+    while let Some(idx) = queue.pop_front() {
+        for adj in adjecent_idxs(map, idx) {
+            if !reachable[adj] && is_walkable(&map.tiles[adj]) {
+                reachable[adj] = true;
+                queue.push_back(adj);
+            }
+        }
+    }
+    let reaches_other_room = rooms
+        .iter()
+        .filter(|r| r.pos != room.pos)
+        .any(|r| r.cells().any(|c| reachable[map.idx(c)]));
+    if reaches_other_room {
+        return None;
+    }
+
+    let open =
+        |idx: usize| map.tiles[idx] == Tile::Dirt && !near_vault(rooms, map.idx_to_vec2(idx));
+    let mut came_from = vec![None; map.tiles.len()];
+    for idx in room_idxs.iter() {
+        for adj in adjecent_idxs(map, *idx) {
+            if came_from[adj].is_none() && open(adj) {
+                came_from[adj] = Some(adj);
+                queue.push_back(adj);
+            }
+        }
+    }
+
+    while let Some(idx) = queue.pop_front() {
+        let adjecent = adjecent_idxs(map, idx);
+        if adjecent
+            .iter()
+            .any(|a| map.tiles[*a] == Tile::Floor && !reachable[*a])
+        {
+            let mut dug = vec![idx];
+            let mut current = idx;
+            while came_from[current] != Some(current) {
+                current = came_from[current]?;
+                dug.push(current);
+            }
+            return Some(dug);
+        }
+
+        for adj in adjecent {
+            let touches_reachable = adjecent_idxs(map, adj).iter().any(|a| reachable[*a]);
+            if came_from[adj].is_some() || !open(adj) || touches_reachable {
+                continue;
+            }
+            came_from[adj] = Some(idx);
+            queue.push_back(adj);
+        }
+    }
 
-pub fn generate_dungeon(map: &mut Map) -> Vec<(Vec2, Tile)> {
-    dungeon_1(map)
+    None
 }
 
 /**
- * Returns a Vec of size n of various Room structs within the given bounds.
+ * Returns a Vec of up to n Room structs with the given sizes and shapes within
+ * the given bounds, next to the already placed rooms. Gives up early when a
+ * room can't be placed, so crowded parameters produce fewer rooms instead of
+ * looping forever.
  */
-fn generate_rooms(amount: usize, bounds: Vec2) -> Vec<Room> {
-    let mut placed_rooms: Vec<Room> = Vec::new();
+fn generate_rooms(
+    amount: usize,
+    bounds: Vec2,
+    sizes: &[Vec2],
+    shapes: RoomShapes,
+    mut placed_rooms: Vec<Room>,
+) -> Vec<Room> {
+    let target = placed_rooms.len() + amount;
 
-    while placed_rooms.len() < amount {
-        let room_size = ROOM_SIZES[rand::gen_range(0, ROOM_SIZES.len())];
+    while placed_rooms.len() < target {
+        let (room_size, mask) = shapes.pick(sizes);
         let mut found_empty_spot = false;
         let mut attemps = 0;
         while !found_empty_spot && attemps < ROOM_GENERATION_ATTEMPTS {
+            attemps += 1;
             let pos = vec2(
                 rand::gen_range(0.0, bounds.x).floor(),
                 rand::gen_range(0.0, bounds.y).floor(),
             );
-            if pos.x + room_size.x >= GAME_WIDTH || pos.y + room_size.y >= GAME_HEIGHT {
-                attemps += 1;
+            if pos.x + room_size.x >= bounds.x || pos.y + room_size.y >= bounds.y {
                 continue;
             }
 
-            let room = Room::new(pos, room_size);
-            found_empty_spot = !placed_rooms.iter().any(|r| r.intersects(&room));
+            let room = Room::new(pos, room_size, mask.clone());
+            found_empty_spot = !overlaps(&placed_rooms, &room);
 
             if found_empty_spot {
                 placed_rooms.push(room);
             }
         }
+
+        if !found_empty_spot {
+            break;
+        }
     }
 
     placed_rooms
 }
 
 /**
- * Takes a room and a range of x and y values. For each x and y value it checks
- *  if the tile at that the door_pos is "empty" and the tile at the other_room_pos
- * is floor. If so it stores the index of the door_pos tile. Finally it returns at
- * random one of the stored indices. If no doors were found it returns None.
+ * Takes a room and a direction. For each cell on the room's outline facing
+ * that direction it checks if the tile one step further is "empty" and the
+ * tile two steps further is floor that doesn't belong to the room itself or a
+ * vault. If so it stores the index of the empty tile. Returns all stored
+ * indices.
  */
-fn generate_doors(
-    map: &mut Map,
-    room: &Room,
-    x_max: Range<i32>,
-    y_max: Range<i32>,
-    door_pos: Vec2,
-    other_room_pos: Vec2,
-) -> Option<usize> {
+fn door_candidates(map: &Map, rooms: &[Room], room: &Room, dir: Vec2) -> Vec<usize> {
     let mut group = Vec::new();
-    for x in x_max {
-        for y in y_max.clone() {
-            let door_lookup_pos = vec2(
-                room.pos.x + x as f32 + door_pos.x,
-                room.pos.y + y as f32 + door_pos.y,
-            );
-            let maybe_door_tile = get_tile_at_pos(map, door_lookup_pos);
-            let maybe_other_room_tile = get_tile_at_pos(
-                map,
-                vec2(
-                    room.pos.x + x as f32 + other_room_pos.x,
-                    room.pos.y + y as f32 + other_room_pos.y,
-                ),
-            );
-            if maybe_door_tile.is_some() && maybe_other_room_tile.is_some() {
-                let tile_space = maybe_door_tile.unwrap();
-                let tile_maybe_connection = maybe_other_room_tile.unwrap();
-                if !is_room(tile_space) && is_room(tile_maybe_connection) {
-                    group.push(map.idx(door_lookup_pos));
-                }
+    for cell in room.cells() {
+        let door_lookup_pos = cell + dir;
+        let other_room_pos = cell + dir * 2.0;
+        let into_vault = rooms
+            .iter()
+            .any(|r| r.entrances.is_some() && r.contains(other_room_pos));
+        if room.contains(door_lookup_pos) || room.contains(other_room_pos) || into_vault {
+            continue;
+        }
+
+        let maybe_door_tile = get_tile_at_pos(map, door_lookup_pos);
+        let maybe_other_room_tile = get_tile_at_pos(map, other_room_pos);
+        if let (Some(tile_space), Some(tile_maybe_connection)) =
+            (maybe_door_tile, maybe_other_room_tile)
+        {
+            if !is_room(tile_space) && is_room(tile_maybe_connection) {
+                group.push(map.idx(door_lookup_pos));
             }
         }
     }
 
-    if group.len() > 0 {
-        let chosen = rand::gen_range(0, group.len());
-        map.tiles[group[chosen]] = if rand::gen_range(0.0, 1.0) > 0.5 {
-            Tile::Door
-        } else {
-            Tile::Floor
-        };
-        Some(group[chosen])
+    group
+}
+
+/**
+ * Picks one of the candidates at random and turns it into either a door or an
+ * opening. A few of the doors are left open. If there are no candidates it
+ * returns None.
+ */
+fn generate_doors(map: &mut Map, candidates: &[usize], door_probability: f32) -> Option<usize> {
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let chosen = candidates[rand::gen_range(0, candidates.len())];
+    map.tiles[chosen] = if rand::gen_range(0.0, 1.0) < door_probability {
+        match rand::gen_range(0, 4) {
+            0 => Tile::Door(DoorState::Open),
+            _ => Tile::Door(DoorState::Closed),
+        }
     } else {
-        None
+        Tile::Floor
+    };
+    Some(chosen)
+}
+
+/**
+ * Turns doors that don't sit between walls on two opposite sides, like the
+ * ones in corridor corners, into openings. So are doors next to a door that
+ * was kept, going row by row. Returns the doors that were turned.
+ */
+fn settle_doors(map: &mut Map) -> Vec<usize> {
+    let mut turned = Vec::new();
+    for idx in 0..map.tiles.len() {
+        if !matches!(map.tiles[idx], Tile::Door(_)) {
+            continue;
+        }
+
+        let framed = door_orientation(wall_mask(map, idx)).is_some();
+        let crowded = surrounding_idxs(map, idx)
+            .iter()
+            .any(|s| matches!(map.tiles[*s], Tile::Door(_)) && *s < idx);
+        if !framed || crowded {
+            map.tiles[idx] = Tile::Floor;
+            turned.push(idx);
+        }
     }
+
+    turned
 }
 
 /**
  * Depth first search to find all tiles that are not connected to any room
  */
-fn dfs(map: &mut Map, visited: &mut Vec<usize>, idx: usize) {
-    if visited.len() > CORRIDOR_MAX_LENGTH {
+fn dfs(map: &mut Map, visited: &mut Vec<usize>, idx: usize, max_length: usize) {
+    if visited.len() > max_length {
         return;
     }
     let mut adjecent = adjecent_idxs(map, idx);
@@ -127,44 +554,114 @@ fn dfs(map: &mut Map, visited: &mut Vec<usize>, idx: usize) {
             .filter(|i| **i != idx)
             .any(|i| visited.contains(i));
         if !adjecent_to_any_visited && !is_adjecent_to_room(map, *adj) {
-            dfs(map, visited, *adj)
+            dfs(map, visited, *adj, max_length)
         }
     }
 }
 
 /**
- * Generate a sparse dungeon with rooms and corridors
+ * Picks the two rooms furthest apart in the room graph as start and goal, puts
+ * stairs in their centers and stores the rooms between them as the critical
+ * path. With an entry the room around it is the start, the up stairs go right
+ * on the entry and the goal is the room furthest from it. Vaults are never
+ * picked, they keep their kind and their layout.
  */
-fn dungeon_1(map: &mut Map) -> Vec<(Vec2, Tile)> {
-    let mut timeline = Vec::new();
-    map.tiles = vec![Tile::Dirt; (GAME_WIDTH * GAME_HEIGHT) as usize];
+fn place_stairs(map: &mut Map, entry: Option<Vec2>) {
+    let graph = RoomGraph::new(map);
+    let allowed = |r: usize| map.rooms[r].kind != RoomKind::Vault;
+    let rooms = match entry {
+        Some(entry) => (0..map.rooms.len())
+            .find(|r| allowed(*r) && map.rooms[*r].contains(entry))
+            .map(|start| {
+                let (distances, _) = graph.distances(start);
+                let goal = (0..distances.len())
+                    .filter(|r| allowed(*r))
+                    .max_by_key(|r| distances[*r])
+                    .unwrap_or(start);
+                (start, goal)
+            }),
+        None => graph.furthest_rooms(allowed),
+    };
+    let (start, goal) = match rooms {
+        Some(rooms) => rooms,
+        None => return,
+    };
+
+    map.start_room = Some(start);
+    map.goal_room = Some(goal);
+    map.critical_path = graph.path(start, goal);
+    map.rooms[start].kind = RoomKind::Start;
+    map.rooms[goal].kind = RoomKind::Goal;
+
+    let up = entry.unwrap_or_else(|| map.rooms[start].center());
+    let mut down = map.rooms[goal].center();
+    if down == up {
+        down.x += 1.0;
+    }
+    let (up, down) = (map.idx(up), map.idx(down));
+    map.tiles[up] = Tile::StairsUp;
+    map.tiles[down] = Tile::StairsDown;
+}
 
+/**
+ * Lays out the rooms, corridors and doors of a sparse dungeon, with vaults if
+ * asked for. Returns the rooms and doors, or None if a vault didn't fit or
+ * none of the entrances of a vault could be connected.
+ */
+fn lay_out(
+    map: &mut Map,
+    params: &GeneratorParams,
+    vaults_wanted: bool,
+    entry: Option<Vec2>,
+    trace: &mut GenerationTrace,
+    timeline: &mut Vec<(Vec2, Tile)>,
+) -> Option<(Vec<Room>, Vec<usize>)> {
     // place rooms
-    let rooms = generate_rooms(rand::gen_range(8, 12), map.size);
+    let room_count = rand::gen_range(params.min_rooms, params.max_rooms.max(params.min_rooms + 1));
+    // the entry continues the floor above, so it gets a room before anything
+    // else
+    let entry_rooms = entry
+        .map(|entry| {
+            let size = vec2(ENTRY_ROOM_SIZE, ENTRY_ROOM_SIZE);
+            let pos = (entry - size / 2.0)
+                .floor()
+                .clamp(Vec2::ZERO, map.size - size - 1.0);
+            let len = ((size.x + 1.0) * (size.y + 1.0)) as usize;
+            vec![Room::new(pos, size, vec![true; len])]
+        })
+        .unwrap_or_default();
+    let (placed_rooms, vault_spawns) = match vaults_wanted {
+        true => place_vaults(&vaults(), map.size, entry_rooms)?,
+        false => (entry_rooms, Vec::new()),
+    };
+    let rooms = generate_rooms(
+        room_count,
+        map.size,
+        params.room_sizes.sizes(),
+        params.room_shapes,
+        placed_rooms,
+    );
     rooms.iter().for_each(|r| {
-        let w = r.size.x as usize;
-        let h = r.size.y as usize;
-        for x in 0..=w {
-            for y in 0..=h {
-                let tile = match (x, y) {
-                    _ if x == 0 || x == w || y == 0 || y == h => Tile::Floor,
-                    _ => Tile::Floor,
-                };
-
-                let idx = map.idx_xy(r.pos.x as usize + x, r.pos.y as usize + y);
-                map.tiles[idx] = tile.clone();
-                timeline.push((vec2(x as f32, y as f32), tile));
-            }
+        for cell in r.cells() {
+            let idx = map.idx(cell);
+            map.tiles[idx] = Tile::Floor;
+            timeline.push((cell - r.pos, Tile::Floor));
         }
     });
+    for (pos, kind) in vault_spawns {
+        let pos = map.idx(pos);
+        map.spawns.push(Spawn { kind, pos });
+    }
 
     // place corridors
     let starting_points = neighbourless_idxs(&map);
+    trace.corridor_starts = starting_points.clone();
     let mut corridors = Vec::new();
     for start in starting_points.iter() {
         let mut visited: Vec<usize> = Vec::new();
-        dfs(map, &mut visited, *start);
+        dfs(map, &mut visited, *start, params.corridor_max_length);
         // println!("Visited {:?}", visited);
+        trace.dfs_order.extend(visited.iter());
         visited.iter().for_each(|v| {
             map.tiles[*v] = Tile::Floor;
             timeline.push((map.idx_to_vec2(*start), Tile::Floor));
@@ -173,48 +670,24 @@ fn dungeon_1(map: &mut Map) -> Vec<(Vec2, Tile)> {
     }
 
     let mut doors = Vec::new();
-    // group possible doors by room edge and pick one for each edge of each room
-    rooms.iter().for_each(|r| {
-        let w = r.size.x as i32;
-        let h = r.size.y as i32;
-
-        // traverse bottom
-        doors.push(generate_doors(
-            map,
-            r,
-            0..w,
-            0..1,
-            vec2(0.0, -1.0),
-            vec2(0.0, -2.0),
-        ));
-        // traverse top
-        doors.push(generate_doors(
-            map,
-            r,
-            0..w,
-            h..(h + 1),
-            vec2(0.0, 1.0),
-            vec2(0.0, 2.0),
-        ));
-        // traverse left
-        doors.push(generate_doors(
-            map,
-            r,
-            0..1,
-            0..h,
-            vec2(-1.0, 0.0),
-            vec2(-2.0, 0.0),
-        ));
-        // traverse right
-        doors.push(generate_doors(
-            map,
-            r,
-            w..(w + 1),
-            0..h,
-            vec2(1.0, 0.0),
-            vec2(2.0, 0.0),
-        ));
-    });
+    // group possible doors by the side of the room they are on and pick one for
+    // each side of each room
+    rooms
+        .iter()
+        .filter(|r| r.entrances.is_none())
+        .for_each(|r| {
+            let sides = [
+                vec2(0.0, -1.0),
+                vec2(0.0, 1.0),
+                vec2(-1.0, 0.0),
+                vec2(1.0, 0.0),
+            ];
+            for dir in sides {
+                let group = door_candidates(map, &rooms, r, dir);
+                trace.door_candidates.extend(group.iter());
+                doors.push(generate_doors(map, &group, params.door_probability));
+            }
+        });
     let doors = doors
         .iter()
         .filter(|d| d.is_some())
@@ -235,6 +708,71 @@ fn dungeon_1(map: &mut Map) -> Vec<(Vec2, Tile)> {
         for c in corridor.iter() {
             map.tiles[*c] = Tile::Dirt;
         }
+        if !corridor.is_empty() {
+            trace.pruned_corridors.push(corridor.clone());
+        }
+    }
+
+    // connect vaults through their entrances only, now that only connected
+    // corridors are left
+    let mut doors = doors;
+    for r in rooms.iter() {
+        let mut connected = false;
+        for entrance in r.entrances.iter().flatten() {
+            if let Some(dug) = connect_entrance(map, &rooms, r, *entrance) {
+                dug.iter().for_each(|d| map.tiles[*d] = Tile::Floor);
+                let door = map.idx(*entrance);
+                map.tiles[door] = Tile::Door(DoorState::Closed);
+                doors.push(door);
+                connected = true;
+            }
+        }
+        // a vault nothing leads into can't be entered at all
+        if r.entrances.is_some() && !connected {
+            return None;
+        }
+    }
+
+    Some((rooms, doors))
+}
+
+/**
+ * Generate a sparse dungeon with rooms and corridors. Layouts where the vaults
+ * don't all fit or can't all be entered are thrown away and laid out again,
+ * and if that keeps failing the dungeon is made without vaults.
+ */
+fn dungeon_1(map: &mut Map, params: &GeneratorParams, entry: Option<Vec2>) -> GenerationTrace {
+    let mut attempts = 0;
+    let (rooms, mut doors, mut trace, mut timeline) = loop {
+        attempts += 1;
+        let vaults_wanted = params.vaults && attempts <= LAYOUT_ATTEMPTS;
+        let mut timeline = Vec::new();
+        let mut trace = GenerationTrace::default();
+        *map = Map {
+            seed: map.seed,
+            ..Map::new(map.size)
+        };
+        let layout = lay_out(map, params, vaults_wanted, entry, &mut trace, &mut timeline);
+        if let Some((rooms, doors)) = layout {
+            break (rooms, doors, trace, timeline);
+        }
+        if attempts == LAYOUT_ATTEMPTS {
+            warn!(
+                "no room for the vaults after {} layouts, leaving them out",
+                LAYOUT_ATTEMPTS
+            );
+        }
+    };
+
+    // the entry room has to lead somewhere, or the floor would end where it
+    // starts
+    if entry.is_some() {
+        if let Some(dug) = connect_room(map, &rooms, &rooms[0]) {
+            dug.iter().for_each(|d| map.tiles[*d] = Tile::Floor);
+            let door = dug[dug.len() - 1];
+            map.tiles[door] = Tile::Door(DoorState::Closed);
+            doors.push(door);
+        }
     }
 
     // add walls
@@ -256,20 +794,59 @@ fn dungeon_1(map: &mut Map) -> Vec<(Vec2, Tile)> {
         map.tiles[*w] = Tile::Wall;
         timeline.push((map.idx_to_vec2(*w), Tile::Wall));
     });
+    let turned = settle_doors(map);
+    doors.retain(|d| !turned.contains(d));
 
-    let mut textures = vec![AtlasTile::from(vec2(7.0, 0.0)); (GAME_WIDTH * GAME_HEIGHT) as usize];
-    map.tiles.iter().enumerate().for_each(|(idx, t)| match t {
-        Tile::Wall => {
-            let surrounding = surrounding_idxs(&map, idx);
-            let atlas_pos = get_wall_atlas_pos(&map.tiles, &surrounding);
-            textures[idx] = AtlasTile::from(atlas_pos);
-        }
-        Tile::Floor => textures[idx] = AtlasTile::from(vec2(8.0, 8.0)),
-        Tile::Door => textures[idx] = AtlasTile::from(vec2(6.0, 2.0)),
-        Tile::Dirt => textures[idx] = AtlasTile::from(vec2(9.0, 6.0)),
-        _ => {}
-    });
-    map.draw_tiles = textures;
+    map.rooms = rooms;
+    place_stairs(map, entry);
+    place_locks(map, params.lock_count);
+    classify_rooms(map);
+    place_secret_rooms(map, params.secret_rooms);
+    decorate_rooms(map);
+    mount_torches(map);
+    debug_assert!(is_solvable(map));
+    place_spawns(map, params.spawn_density, params.spawn_min_distance);
+    assign_materials(map);
+    refresh_draw_tiles(map);
 
-    timeline
+    trace.steps = timeline;
+    trace.doors = doors;
+    trace
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+    #[test]
+    fn vaults_are_all_placed_entered_and_kept() {
+        let expected = vaults().iter().map(|v| v.count).sum::<usize>();
+        for seed in 0..100 {
+            let mut map = Map::new(vec2(GAME_WIDTH, GAME_HEIGHT));
+            generate_dungeon(&mut map, seed, &GeneratorParams::default(), None);
+            let vault_rooms = map
+                .rooms
+                .iter()
+                .filter(|r| r.entrances.is_some())
+                .collect::<Vec<&Room>>();
+            assert_eq!(vault_rooms.len(), expected, "seed {}", seed);
+            for room in vault_rooms {
+                assert!(
+                    room.kind == RoomKind::Vault,
+                    "vault lost with seed {}",
+                    seed
+                );
+                let stairs = room
+                    .cells()
+                    .any(|c| matches!(map.tiles[map.idx(c)], Tile::StairsUp | Tile::StairsDown));
+                assert!(!stairs, "stairs in a vault with seed {}", seed);
+                let entered = room.entrances.iter().flatten().any(|e| {
+                    matches!(map.tiles[map.idx(*e)], Tile::Door(_))
+                        || is_floor(&map.tiles[map.idx(*e)])
+                });
+                assert!(entered, "sealed vault with seed {}", seed);
+            }
+        }
+    }
 }
@@ -1,11 +1,9 @@
 use macroquad::prelude::*;
 use std::ops::Add;
 
-use crate::components::{
-    Map, Tile, WALL_BOTTOM_END, WALL_BOTTOM_LEFT_CORNER, WALL_BOTTOM_RIGHT_CORNER, WALL_CROSS,
-    WALL_DOWNRIGHT_T, WALL_HOR_LINE, WALL_LEFT_END, WALL_LEFT_LYING_T, WALL_RIGHT_END,
-    WALL_RIGHT_LYING_T, WALL_TOP_END, WALL_TOP_LEFT_CORNER, WALL_TOP_RIGHT_CORNER, WALL_UPRIGHT_T,
-    WALL_VERT_LINE,
+use crate::{
+    components::{AtlasTile, DoorState, Map, Orientation, Tile},
+    tileset::tileset,
 };
 
 pub fn is_floor(tile: &Tile) -> bool {
@@ -22,44 +20,99 @@ pub fn is_room(tile: &Tile) -> bool {
     }
 }
 
+/**
+ * Whether a tile can be walked on.
+ */
+pub fn is_walkable(tile: &Tile) -> bool {
+    matches!(
+        tile,
+        Tile::Floor
+            | Tile::Door(DoorState::Open | DoorState::Closed)
+            | Tile::StairsUp
+            | Tile::StairsDown
+    )
+}
+
+/**
+ * Whether a tile blocks line of sight. Only open doors can be seen through.
+ */
+pub fn is_opaque(tile: &Tile) -> bool {
+    matches!(
+        tile,
+        Tile::Wall | Tile::Door(DoorState::Closed | DoorState::Locked(_) | DoorState::Secret)
+    )
+}
+
+/**
+ * Whether a tile looks like a wall. Secret doors are drawn as part of the wall
+ * they hide in.
+ */
+pub fn is_wall(tile: &Tile) -> bool {
+    matches!(tile, Tile::Wall | Tile::Door(DoorState::Secret))
+}
+
+/**
+ * Scrambles the bits of h, so nearby inputs give unrelated outputs.
+ */
+pub fn mix(mut h: u64) -> u64 {
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+/**
+ * Combines values into a single hash. Used wherever something has to look
+ * random but come out the same every time, without touching the global rng.
+ */
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e3779b97f4a7c15, |h, value| mix(h ^ value))
+}
+
+/**
+ * Index of the first tile of the given kind.
+ */
+pub fn find_tile(map: &Map, tile: Tile) -> Option<usize> {
+    map.tiles.iter().position(|t| *t == tile)
+}
+
 pub fn get_tile_at_pos(map: &Map, pos: Vec2) -> Option<&Tile> {
     let idx = map.idx(pos);
     map.tiles.get(idx)
 }
 
-pub fn surrounding_tiles(map: &Map, idx: usize) -> Vec<Option<&Tile>> {
-    let adjecent_vecs = vec![
-        vec2(-1.0, -1.0), // top left corner
-        vec2(0.0, -1.0),  // top center
-        vec2(1.0, -1.0),  // top right corner
-        vec2(-1.0, 0.0),  // left
-        vec2(1.0, 0.0),   // right
-        vec2(-1.0, 1.0),  // bottom left corner
-        vec2(0.0, 1.0),   // bottom center
-        vec2(1.0, 1.0),   // bottom right corner
-    ];
+/// Offsets of the eight neighbours of a cell, row by row.
+const NEIGHBOURS: [Vec2; 8] = [
+    vec2(-1.0, -1.0), // top left corner
+    vec2(0.0, -1.0),  // top center
+    vec2(1.0, -1.0),  // top right corner
+    vec2(-1.0, 0.0),  // left
+    vec2(1.0, 0.0),   // right
+    vec2(-1.0, 1.0),  // bottom left corner
+    vec2(0.0, 1.0),   // bottom center
+    vec2(1.0, 1.0),   // bottom right corner
+];
 
-    adjecent_vecs
+pub fn surrounding_tiles(map: &Map, idx: usize) -> Vec<Option<&Tile>> {
+    NEIGHBOURS
         .iter()
         .map(|v| map.tile_at_pos(map.idx_to_vec2(idx).add(*v)))
         .collect()
 }
-pub fn surrounding_idxs(map: &Map, idx: usize) -> Vec<usize> {
-    let adjecent_vecs = vec![
-        vec2(-1.0, -1.0), // top left corner
-        vec2(0.0, -1.0),  // top center
-        vec2(1.0, -1.0),  // top right corner
-        vec2(-1.0, 0.0),  // left
-        vec2(1.0, 0.0),   // right
-        vec2(-1.0, 1.0),  // bottom left corner
-        vec2(0.0, 1.0),   // bottom center
-        vec2(1.0, 1.0),   // bottom right corner
-    ];
 
-    adjecent_vecs
+/**
+ * Indices of the neighbours of idx that lie within the map. Cells on the map
+ * edge have fewer of them, nothing wraps around to the other side.
+ */
+pub fn surrounding_idxs(map: &Map, idx: usize) -> Vec<usize> {
+    NEIGHBOURS
         .iter()
-        .map(|v| map.idx(map.idx_to_vec2(idx).add(*v)))
-        .filter(|u| u < &map.tiles.len())
+        .map(|v| map.idx_to_vec2(idx).add(*v))
+        .filter(|pos| map.in_bounds(*pos))
+        .map(|pos| map.idx(pos))
         .collect()
 }
 
@@ -87,37 +140,76 @@ pub fn adjecent_idxs(map: &Map, idx: usize) -> Vec<usize> {
         .collect()
 }
 
-pub fn get_wall_atlas_pos(tiles: &Vec<Tile>, surrounding: &Vec<usize>) -> Vec2 {
-    let matches = surrounding
+/**
+ * Bit mask of the walls around idx with one bit per neighbour, in the same
+ * order as surrounding_idxs: top left is the lowest bit, bottom right the
+ * highest. Neighbours outside the map count as no wall.
+ */
+pub fn wall_mask(map: &Map, idx: usize) -> u8 {
+    let pos = map.idx_to_vec2(idx);
+    NEIGHBOURS
         .iter()
-        .map(|idx| tiles.get(*idx))
-        .map(|t| match t {
-            Some(Tile::Wall) => true,
-            _ => false,
-        })
-        .collect::<Vec<bool>>();
-
-    match matches[..] {
-        // end pieces
-        [_, false, _, true, false, _, false, _] => WALL_RIGHT_END,
-        [_, false, _, false, true, _, false, _] => WALL_LEFT_END,
-        [_, false, _, false, false, _, true, _] => WALL_TOP_END,
-        [_, true, _, false, false, _, false, _] => WALL_BOTTOM_END,
-        // connectors
-        [_, true, _, true, true, _, true, _] => WALL_CROSS,
-        [_, true, _, false, false, _, true, _] => WALL_VERT_LINE,
-        [_, false, _, true, true, _, false, _] => WALL_HOR_LINE,
-        [_, false, _, true, true, _, true, _] => WALL_UPRIGHT_T,
-        [_, true, _, true, true, _, false, _] => WALL_DOWNRIGHT_T,
-        [_, true, _, true, false, _, true, _] => WALL_RIGHT_LYING_T,
-        [_, true, _, false, true, _, true, _] => WALL_LEFT_LYING_T,
-        // corners
-        [_, false, _, false, true, _, true, _] => WALL_TOP_LEFT_CORNER,
-        [_, false, _, true, false, _, true, _] => WALL_TOP_RIGHT_CORNER,
-        [_, true, _, false, true, _, false, _] => WALL_BOTTOM_LEFT_CORNER,
-        [_, true, _, true, false, _, false, _] => WALL_BOTTOM_RIGHT_CORNER,
-        _ => vec2(8.0, 0.0),
+        .enumerate()
+        .filter(|(_, v)| map.tile_at_pos(pos + **v).is_some_and(is_wall))
+        .fold(0, |mask, (bit, _)| mask | 1 << bit)
+}
+
+/**
+ * Which way a door at a cell with the given wall mask runs: horizontal between
+ * walls to the left and right, vertical between walls above and below. None if
+ * the walls don't frame it on two opposite sides, like in a corridor corner.
+ */
+pub fn door_orientation(wall_mask: u8) -> Option<Orientation> {
+    let wall = |bit: u8| wall_mask & 1 << bit != 0;
+    // bits of N, W, E and S in NEIGHBOURS
+    let (n, w, e, s) = (wall(1), wall(3), wall(4), wall(6));
+    match (n, w, e, s) {
+        (false, true, true, false) => Some(Orientation::Horizontal),
+        (true, false, false, true) => Some(Orientation::Vertical),
+        _ => None,
+    }
+}
+
+/**
+ * Returns the atlas position used to draw the tile at idx.
+ */
+pub fn get_atlas_pos(map: &Map, idx: usize) -> Vec2 {
+    // spread the cells out, so neighbouring seeds don't just shift the variants
+    let variant = map.seed ^ (idx as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    tileset().atlas_pos(
+        &map.tiles[idx],
+        wall_mask(map, idx),
+        map.materials[idx],
+        variant,
+    )
+}
+
+/**
+ * Recomputes the draw tiles of the whole map.
+ */
+pub fn refresh_draw_tiles(map: &mut Map) {
+    map.draw_tiles = (0..map.tiles.len())
+        .map(|idx| AtlasTile::from(get_atlas_pos(map, idx)))
+        .collect();
+    map.touch();
+}
+
+/**
+ * Recomputes the draw tiles of the given indices and their surrounding tiles,
+ * since a changed tile can change how the walls next to it are autotiled.
+ */
+pub fn update_draw_tiles(map: &mut Map, idxs: &[usize]) {
+    let mut dirty = idxs.to_vec();
+    for idx in idxs.iter() {
+        dirty.extend(surrounding_idxs(map, *idx));
     }
+    dirty.sort_unstable();
+    dirty.dedup();
+
+    for idx in dirty {
+        map.draw_tiles[idx] = AtlasTile::from(get_atlas_pos(map, idx));
+    }
+    map.touch();
 }
 
 pub fn is_adjecent_to_room(map: &Map, idx: usize) -> bool {
@@ -152,3 +244,47 @@ pub fn neighbourless_idxs(map: &Map) -> Vec<usize> {
 
     starting_points
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walls(size: Vec2) -> Map {
+        let mut map = Map::new(size);
+        map.tiles = vec![Tile::Wall; map.tiles.len()];
+        map
+    }
+
+    #[test]
+    fn wall_mask_in_the_middle() {
+        let map = walls(vec2(3.0, 3.0));
+        assert_eq!(wall_mask(&map, map.idx_xy(1, 1)), 0b1111_1111);
+    }
+
+    #[test]
+    fn wall_mask_on_corners() {
+        let map = walls(vec2(3.0, 3.0));
+        assert_eq!(wall_mask(&map, map.idx_xy(0, 0)), 0b1101_0000);
+        assert_eq!(wall_mask(&map, map.idx_xy(2, 0)), 0b0110_1000);
+        assert_eq!(wall_mask(&map, map.idx_xy(0, 2)), 0b0001_0110);
+        assert_eq!(wall_mask(&map, map.idx_xy(2, 2)), 0b0000_1011);
+    }
+
+    #[test]
+    fn wall_mask_on_edges_does_not_wrap() {
+        let map = walls(vec2(3.0, 3.0));
+        assert_eq!(wall_mask(&map, map.idx_xy(1, 0)), 0b1111_1000);
+        assert_eq!(wall_mask(&map, map.idx_xy(0, 1)), 0b1101_0110);
+        assert_eq!(wall_mask(&map, map.idx_xy(2, 1)), 0b0110_1011);
+        assert_eq!(wall_mask(&map, map.idx_xy(1, 2)), 0b0001_1111);
+    }
+
+    #[test]
+    fn wall_mask_counts_secret_doors() {
+        let mut map = Map::new(vec2(3.0, 3.0));
+        let (secret, door) = (map.idx_xy(1, 0), map.idx_xy(2, 1));
+        map.tiles[secret] = Tile::Door(DoorState::Secret);
+        map.tiles[door] = Tile::Door(DoorState::Closed);
+        assert_eq!(wall_mask(&map, map.idx_xy(1, 1)), 0b0000_0010);
+    }
+}
@@ -0,0 +1,113 @@
+use macroquad::prelude::Color;
+
+use crate::{
+    components::{Map, PropKind, SpawnKind},
+    draw::DARK,
+    fov::field_of_view,
+    level_utils::{adjecent_idxs, is_walkable},
+};
+
+/// Light every cell gets even when no light source reaches it.
+const AMBIENT_LIGHT: f32 = 0.15;
+const TORCH_RADIUS: i32 = 8;
+// treasures and keys glow a little, so they can be spotted in dark rooms
+const GLOW_RADIUS: i32 = 3;
+const GLOW_INTENSITY: f32 = 0.5;
+
+/**
+ * Where the light of a torch on a wall comes from: the cell in front of it,
+ * on the side of the room it hangs in. None if nothing walkable is next to it.
+ */
+fn wall_torch_origin(map: &Map, idx: usize) -> Option<usize> {
+    let open = adjecent_idxs(map, idx)
+        .into_iter()
+        .filter(|a| is_walkable(&map.tiles[*a]))
+        .collect::<Vec<usize>>();
+    let in_room = |a: &&usize| map.rooms.iter().any(|r| r.contains(map.idx_to_vec2(**a)));
+    open.iter().find(in_room).or(open.first()).copied()
+}
+
+/**
+ * Cells that give off light along with how far and how bright. Torches on
+ * walls light the room from the cell in front of them, so the wall doesn't
+ * block their light.
+ */
+fn light_sources(map: &Map) -> Vec<(usize, i32, f32)> {
+    let torches = map.props.iter().filter_map(|p| match p.kind {
+        PropKind::Torch => Some((p.pos, TORCH_RADIUS, 1.0)),
+        PropKind::WallTorch => Some((wall_torch_origin(map, p.pos)?, TORCH_RADIUS, 1.0)),
+        _ => None,
+    });
+    let glows = map
+        .spawns
+        .iter()
+        .filter(|s| matches!(s.kind, SpawnKind::Treasure | SpawnKind::Key(_)))
+        .map(|s| (s.pos, GLOW_RADIUS, GLOW_INTENSITY));
+
+    torches.chain(glows).collect()
+}
+
+/**
+ * How lit every cell of the map is, from 0 for pitch black to 1 for fully lit.
+ * Every source lights the cells it can see within its radius, fading out
+ * towards the edge, so walls cast shadows. Overlapping light adds up.
+ */
+pub fn light_map(map: &Map) -> Vec<f32> {
+    let mut light = vec![AMBIENT_LIGHT; map.tiles.len()];
+    for (pos, radius, intensity) in light_sources(map) {
+        let origin = map.idx_to_vec2(pos);
+        let visible = field_of_view(map, origin, radius);
+        for (idx, _) in visible.iter().enumerate().filter(|(_, v)| **v) {
+            let distance = map.idx_to_vec2(idx).distance(origin);
+            let falloff = (1.0 - distance / (radius + 1) as f32).max(0.0);
+            light[idx] = (light[idx] + falloff * intensity).min(1.0);
+        }
+    }
+
+    light
+}
+
+/**
+ * Color with the given amount of light, fading into the background as it gets
+ * darker.
+ */
+pub fn shade(color: Color, light: f32) -> Color {
+    let mix = |dark: f32, lit: f32| dark + (lit - dark) * light;
+    Color::new(
+        mix(DARK.r, color.r),
+        mix(DARK.g, color.g),
+        mix(DARK.b, color.b),
+        color.a,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::vec2;
+
+    use super::*;
+    use crate::components::{Prop, Room, Tile};
+
+    #[test]
+    fn wall_torches_light_their_room_only() {
+        // a corridor above a wall, with a room below it
+        let mut map = Map::new(vec2(7.0, 7.0));
+        map.tiles = vec![Tile::Floor; 49];
+        for x in 0..7 {
+            let idx = map.idx_xy(x, 3);
+            map.tiles[idx] = Tile::Wall;
+        }
+        map.rooms
+            .push(Room::new(vec2(0.0, 4.0), vec2(6.0, 2.0), vec![true; 21]));
+        let torch = map.idx_xy(3, 3);
+        map.props.push(Prop {
+            kind: PropKind::WallTorch,
+            pos: torch,
+        });
+
+        let light = light_map(&map);
+        assert!(light[map.idx_xy(3, 5)] > AMBIENT_LIGHT);
+        assert!(light[torch] > AMBIENT_LIGHT);
+        assert_eq!(light[map.idx_xy(3, 1)], AMBIENT_LIGHT);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+
+use macroquad::rand::ChooseRandom;
+
+use crate::{
+    components::{DoorState, Map, Spawn, SpawnKind, Tile},
+    level_utils::{adjecent_idxs, find_tile, is_walkable},
+    pathfinding::{dijkstra_map, PathCosts},
+};
+
+/**
+ * Returns for every tile whether it can be reached from start, where locked
+ * doors can only be passed if their key is in unlocked and the tiles in
+ * blocked can't be passed at all.
+ */
+fn reachable(map: &Map, start: usize, unlocked: &[u8], blocked: &[usize]) -> Vec<bool> {
+    let mut visited = vec![false; map.tiles.len()];
+    let mut queue = VecDeque::from([start]);
+    visited[start] = true;
+
+    while let Some(idx) = queue.pop_front() {
+        for adj in adjecent_idxs(map, idx) {
+            if visited[adj] || blocked.contains(&adj) {
+                continue;
+            }
+            let passable = match map.tiles[adj] {
+                Tile::Door(DoorState::Locked(id)) => unlocked.contains(&id),
+                tile => is_walkable(&tile),
+            };
+            if passable {
+                visited[adj] = true;
+                queue.push_back(adj);
+            }
+        }
+    }
+
+    visited
+}
+
+/**
+ * Turns up to count doors into locked doors and places a matching key for each
+ * of them. Doors that cut the way to the goal stairs are preferred. Locks are
+ * numbered by their distance from the start and every key is placed where it
+ * can be reached with only the keys of earlier locks, so the dungeon is always
+ * solvable.
+ */
+pub fn place_locks(map: &mut Map, count: usize) {
+    let (start, goal) = match (
+        find_tile(map, Tile::StairsUp),
+        find_tile(map, Tile::StairsDown),
+    ) {
+        (Some(start), Some(goal)) => (start, goal),
+        _ => return,
+    };
+
+    let distances = dijkstra_map(map, &[start], &PathCosts::default());
+    let mut doors = map
+        .tiles
+        .iter()
+        .enumerate()
+        .filter(|(idx, t)| {
+            matches!(t, Tile::Door(DoorState::Open | DoorState::Closed))
+                && distances[*idx].is_some()
+        })
+        .map(|(idx, _)| idx)
+        .collect::<Vec<usize>>();
+    doors.shuffle();
+
+    // doors that separate the start from the goal make for the best locks
+    let (mut chosen, others): (Vec<usize>, Vec<usize>) = doors
+        .iter()
+        .partition(|door| !reachable(map, start, &[], &[**door])[goal]);
+    chosen.truncate(count);
+    chosen.extend(others.iter().take(count.saturating_sub(chosen.len())));
+    chosen.sort_by_key(|door| distances[*door]);
+
+    for (id, door) in chosen.iter().enumerate() {
+        let id = id as u8;
+        let unlocked = (0..id).collect::<Vec<u8>>();
+        let region = reachable(map, start, &unlocked, &chosen[id as usize..]);
+        let previous_region = match id {
+            0 => vec![false; map.tiles.len()],
+            _ => reachable(
+                map,
+                start,
+                &unlocked[..id as usize - 1],
+                &chosen[id as usize - 1..],
+            ),
+        };
+
+        let spots = |new_only: bool| {
+            region
+                .iter()
+                .enumerate()
+                .filter(|(idx, r)| {
+                    **r && map.tiles[*idx] == Tile::Floor
+                        && map.spawn_at(*idx).is_none()
+                        && !(new_only && previous_region[*idx])
+                })
+                .map(|(idx, _)| idx)
+                .collect::<Vec<usize>>()
+        };
+        // prefer the area opened up by the previous key
+        let mut candidates = spots(true);
+        if candidates.is_empty() {
+            candidates = spots(false);
+        }
+        let pos = match candidates.choose() {
+            Some(pos) => *pos,
+            None => break,
+        };
+
+        map.tiles[*door] = Tile::Door(DoorState::Locked(id));
+        map.spawns.push(Spawn {
+            kind: SpawnKind::Key(id),
+            pos,
+        });
+    }
+}
+
+/**
+ * Plays through the dungeon from the up stairs, picking up every key that can
+ * be reached and opening its lock, until nothing new can be reached. The
+ * dungeon is solvable if the down stairs and every key were reached.
+ */
+pub fn is_solvable(map: &Map) -> bool {
+    let keys = map
+        .spawns
+        .iter()
+        .filter_map(|s| match s.kind {
+            SpawnKind::Key(id) => Some((id, s.pos)),
+            _ => None,
+        })
+        .collect::<Vec<(u8, usize)>>();
+    let start = match find_tile(map, Tile::StairsUp) {
+        Some(start) => start,
+        None => return keys.is_empty(),
+    };
+
+    let mut unlocked = Vec::new();
+    loop {
+        let region = reachable(map, start, &unlocked, &[]);
+        let found = keys
+            .iter()
+            .filter(|(id, pos)| region[*pos] && !unlocked.contains(id))
+            .map(|(id, _)| *id)
+            .collect::<Vec<u8>>();
+
+        if found.is_empty() {
+            let goal_reached = find_tile(map, Tile::StairsDown).is_none_or(|g| region[g]);
+            return goal_reached && unlocked.len() == keys.len();
+        }
+        unlocked.extend(found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::vec2;
+
+    use super::*;
+    use crate::{
+        level::{generate_dungeon, GeneratorParams},
+        GAME_HEIGHT, GAME_WIDTH,
+    };
+
+    /**
+     * A corridor from the up stairs to the down stairs with a door locked by
+     * key 1 in the middle, and the key at the given cell.
+     */
+    fn corridor(key: usize) -> Map {
+        let mut map = Map::new(vec2(5.0, 1.0));
+        map.tiles = vec![
+            Tile::StairsUp,
+            Tile::Floor,
+            Tile::Door(DoorState::Locked(1)),
+            Tile::Floor,
+            Tile::StairsDown,
+        ];
+        map.spawns.push(Spawn {
+            kind: SpawnKind::Key(1),
+            pos: key,
+        });
+        map
+    }
+
+    #[test]
+    fn key_in_front_of_its_door_is_solvable() {
+        assert!(is_solvable(&corridor(1)));
+    }
+
+    #[test]
+    fn key_behind_its_own_door_is_not_solvable() {
+        assert!(!is_solvable(&corridor(3)));
+    }
+
+    #[test]
+    fn generated_dungeons_are_solvable() {
+        for lock_count in [0, 1, 2, 4, 8] {
+            let params = GeneratorParams {
+                lock_count,
+                ..GeneratorParams::default()
+            };
+            for seed in 0..40 {
+                let mut map = Map::new(vec2(GAME_WIDTH, GAME_HEIGHT));
+                generate_dungeon(&mut map, seed, &params, None);
+                assert!(is_solvable(&map), "seed {} with {} locks", seed, lock_count);
+            }
+        }
+    }
+}
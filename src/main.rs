@@ -1,54 +1,130 @@
 use macroquad::{prelude::*, window};
 
+mod chunks;
 mod components;
+mod decoration;
 use components::*;
 use draw::*;
 mod draw;
+mod dungeon;
+mod editor;
+mod fov;
+mod history;
 use input::*;
 mod input;
 mod level;
 mod level_utils;
+mod lighting;
+mod locks;
+mod map_cache;
+mod pathfinding;
+mod play;
+mod room_graph;
+mod save;
+mod secrets;
+mod shapes;
+mod spawns;
+mod tileset;
+mod ui;
+mod vaults;
 
 pub const TILE_SIZE: f32 = 16.0;
 pub const GAME_WIDTH: f32 = 64.0;
 pub const GAME_HEIGHT: f32 = 64.0;
+const BLOCKS_IMAGE: &str = "assets/blocks.png";
 
 fn window_conf() -> window::Conf {
     window::Conf {
         window_title: "Dungeonmaker".to_owned(),
         window_width: (32.0 * TILE_SIZE) as i32,
         window_height: (32.0 * TILE_SIZE) as i32,
-        window_resizable: false,
-        high_dpi: false,
+        window_resizable: true,
+        high_dpi: true,
         ..Default::default()
     }
 }
 
 #[macroquad::main(window_conf)]
 async fn main() {
-    rand::srand(macroquad::miniquad::date::now() as u64);
-    let dungeon_texture: Texture2D = load_texture("assets/Dungeon.png").await.unwrap();
+    // the tileset has to be loaded before any map is made, so errors are only
+    // reported once there is a world to show them
+    let mut errors = Vec::new();
+    let dungeon_texture = match tileset::load_tileset(tileset::TILESET_PATH).await {
+        Ok((_, texture)) => texture,
+        Err(e) => {
+            errors.push(format!(
+                "Could not load tileset, using the built-in one: {}",
+                e
+            ));
+            let image = &tileset::tileset().image;
+            load_texture(image).await.unwrap_or_else(|e| {
+                errors.push(format!("Could not load {}: {}", image, e));
+                Texture2D::empty()
+            })
+        }
+    };
+    dungeon_texture.set_filter(FilterMode::Nearest);
+    let blocks_texture = load_texture(BLOCKS_IMAGE).await.unwrap_or_else(|e| {
+        errors.push(format!(
+            "Could not load {}, the blocks style stays empty: {}",
+            BLOCKS_IMAGE, e
+        ));
+        Texture2D::empty()
+    });
+    blocks_texture.set_filter(FilterMode::Nearest);
 
     let mut world = World::new(GAME_WIDTH, GAME_HEIGHT);
+    for error in errors {
+        world.report(error);
+    }
+    let mut map_cache = map_cache::MapCache::new();
 
-    let _steps = level::generate_dungeon(&mut world.map);
+    world.seed = level::new_seed();
+    world.generate();
 
     loop {
         clear_background(DARK);
-        let camera = Camera2D::from_display_rect(macroquad::math::Rect {
-            x: world.camera.pos.x,
-            y: world.camera.pos.y,
-            w: window::screen_width() * world.camera.zoom,
-            h: window::screen_height() * world.camera.zoom,
-        });
-        set_camera(&camera);
+        set_camera(&world.camera.camera2d());
 
+        ui::draw_panel(&mut world);
         input(&mut world);
+        world.update_shown_map();
         // timeline.update(&world);
 
-        draw::draw_grid(&world);
-        // timeline.draw(&blocks_texture);
-        world.map.draw(&dungeon_texture);
+        if world.mode == Mode::Chunks {
+            world.chunks.stream(world.camera.viewport());
+            draw::draw_chunks(&world, &dungeon_texture);
+        } else {
+            let view = world.camera.viewport();
+            if world.style == MapStyle::Tileset {
+                map_cache.update(
+                    world.shown_map(),
+                    &dungeon_texture,
+                    view,
+                    world.overlays.lighting,
+                );
+                set_camera(&world.camera.camera2d());
+            }
+
+            if world.overlays.grid {
+                draw::draw_grid(&world);
+            }
+            // timeline.draw(&blocks_texture);
+            match world.style {
+                MapStyle::Tileset => map_cache.draw(view),
+                MapStyle::Blocks => draw::draw_blocks(&world, &blocks_texture),
+            }
+            draw::draw_secrets(&world);
+            draw::draw_overlays(&world);
+        }
+        draw::draw_path_view(&world);
+        draw::draw_fov_view(&world);
+        draw::draw_play_view(&world);
+        draw::draw_editor(&world);
+
+        set_default_camera();
+        draw::draw_minimap(&world);
+        draw::draw_hud(&world);
 
         next_frame().await
     }
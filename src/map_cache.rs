@@ -0,0 +1,233 @@
+use std::{collections::HashSet, hash::Hash};
+
+use macroquad::{
+    camera::{set_camera, Camera2D},
+    color::Color,
+    prelude::{vec2, Rect, Vec2, WHITE},
+    texture::{
+        draw_texture_ex, render_target, DrawTextureParams, FilterMode, RenderTarget, Texture2D,
+    },
+    window::clear_background,
+};
+
+use crate::{
+    components::{AtlasTile, Map, Prop, Spawn, Tile},
+    draw::draw_map_region,
+    lighting::light_map,
+    TILE_SIZE,
+};
+
+/// Cells per side of a cached page.
+const PAGE_SIZE: usize = 32;
+
+/**
+ * Items that are only in one of old and new.
+ */
+fn difference<'a, T: Eq + Hash>(old: &'a [T], new: &'a [T]) -> Vec<&'a T> {
+    let old = old.iter().collect::<HashSet<&T>>();
+    let new = new.iter().collect::<HashSet<&T>>();
+    old.symmetric_difference(&new).copied().collect()
+}
+
+/**
+ * The map baked into textures, one per page of PAGE_SIZE by PAGE_SIZE cells,
+ * so drawing it takes a handful of draw calls instead of one per cell. Pages
+ * are baked once they come into view, and baked again when any of their cells
+ * changed.
+ */
+pub struct MapCache {
+    size: Vec2,
+    pages: Vec<Option<RenderTarget>>,
+    dirty: Vec<bool>,
+    // what the pages were baked from, to find the cells that changed
+    tiles: Vec<Tile>,
+    draw_tiles: Vec<AtlasTile>,
+    spawns: Vec<Spawn>,
+    props: Vec<Prop>,
+    /// Revision of the map the pages were baked from.
+    revision: u64,
+    /// Light map the pages were baked with, empty when they're unlit.
+    light: Vec<f32>,
+}
+
+impl MapCache {
+    pub fn new() -> MapCache {
+        MapCache {
+            size: Vec2::ZERO,
+            pages: Vec::new(),
+            dirty: Vec::new(),
+            tiles: Vec::new(),
+            draw_tiles: Vec::new(),
+            spawns: Vec::new(),
+            props: Vec::new(),
+            revision: 0,
+            light: Vec::new(),
+        }
+    }
+
+    fn pages_x(&self) -> usize {
+        (self.size.x as usize).div_ceil(PAGE_SIZE)
+    }
+
+    fn pages_y(&self) -> usize {
+        (self.size.y as usize).div_ceil(PAGE_SIZE)
+    }
+
+    fn page_of(&self, idx: usize) -> usize {
+        let (x, y) = (idx % self.size.x as usize, idx / self.size.x as usize);
+        (y / PAGE_SIZE) * self.pages_x() + x / PAGE_SIZE
+    }
+
+    /**
+     * The cells a page covers. Pages on the right and bottom edge reach past
+     * the map.
+     */
+    fn page_area(&self, page: usize) -> Rect {
+        let (x, y) = (page % self.pages_x(), page / self.pages_x());
+        let size = PAGE_SIZE as f32;
+        Rect::new(x as f32 * size, y as f32 * size, size, size)
+    }
+
+    /**
+     * Pages overlapping view, given in world pixels.
+     */
+    fn pages_in(&self, view: Rect) -> Vec<usize> {
+        let page = PAGE_SIZE as f32 * TILE_SIZE;
+        let range = |from: f32, to: f32, count: usize| {
+            let first = (from / page).floor().max(0.0) as usize;
+            let last = ((to / page).ceil().max(0.0) as usize).min(count);
+            first..last
+        };
+        let xs = range(view.left(), view.right(), self.pages_x());
+        let pages_x = self.pages_x();
+        range(view.top(), view.bottom(), self.pages_y())
+            .flat_map(|y| xs.clone().map(move |x| y * pages_x + x))
+            .collect()
+    }
+
+    /**
+     * Frees the textures of all pages.
+     */
+    fn clear(&mut self) {
+        for target in self.pages.iter().flatten() {
+            target.delete();
+        }
+        self.pages.clear();
+    }
+
+    /**
+     * Compares the map with what the pages were baked from and marks the
+     * pages of every cell that changed. Light reaches far, so the light map is
+     * worked out again whenever anything changed, and cells it lights
+     * differently are marked too. A map of another size starts over, while a
+     * map of the same revision is skipped without looking at its cells.
+     */
+    fn find_changes(&mut self, map: &Map, lit: bool) {
+        let was_lit = !self.light.is_empty();
+        if map.size == self.size && map.revision == self.revision && lit == was_lit {
+            return;
+        }
+        self.revision = map.revision;
+        if map.size != self.size {
+            self.clear();
+            self.size = map.size;
+            let count = self.pages_x() * self.pages_y();
+            self.pages = vec![None; count];
+            self.dirty = vec![true; count];
+            self.tiles = map.tiles.clone();
+            self.draw_tiles = map.draw_tiles.clone();
+            self.spawns = map.spawns.clone();
+            self.props = map.props.clone();
+            self.light = if lit { light_map(map) } else { Vec::new() };
+            return;
+        }
+
+        let mut changed = (0..map.tiles.len())
+            .filter(|i| {
+                map.tiles[*i] != self.tiles[*i] || map.draw_tiles[*i] != self.draw_tiles[*i]
+            })
+            .collect::<Vec<usize>>();
+        if !changed.is_empty() {
+            self.tiles = map.tiles.clone();
+            self.draw_tiles = map.draw_tiles.clone();
+        }
+        if map.spawns != self.spawns {
+            changed.extend(difference(&self.spawns, &map.spawns).iter().map(|s| s.pos));
+            self.spawns = map.spawns.clone();
+        }
+        if map.props != self.props {
+            changed.extend(difference(&self.props, &map.props).iter().map(|p| p.pos));
+            self.props = map.props.clone();
+        }
+        if !changed.is_empty() || lit != was_lit {
+            let light = if lit { light_map(map) } else { Vec::new() };
+            if light.len() != self.light.len() {
+                changed.extend(0..map.tiles.len());
+            } else {
+                changed.extend((0..light.len()).filter(|i| light[*i] != self.light[*i]));
+            }
+            self.light = light;
+        }
+
+        for idx in changed {
+            let page = self.page_of(idx);
+            self.dirty[page] = true;
+        }
+    }
+
+    /**
+     * Bakes the pages in view, given in world pixels, that changed since they
+     * were last baked, shaded by the light map if lit. Leaves the camera
+     * pointing at the last page, so set it again before drawing anything else.
+     */
+    pub fn update(&mut self, map: &Map, texture: &Texture2D, view: Rect, lit: bool) {
+        self.find_changes(map, lit);
+
+        let size = PAGE_SIZE as f32 * TILE_SIZE;
+        for page in self.pages_in(view) {
+            if !self.dirty[page] {
+                continue;
+            }
+
+            let target = *self.pages[page].get_or_insert_with(|| {
+                let target = render_target(size as u32, size as u32);
+                target.texture.set_filter(FilterMode::Nearest);
+                target
+            });
+            let area = self.page_area(page);
+            set_camera(&Camera2D {
+                target: (area.point() + area.size() / 2.0) * TILE_SIZE,
+                // unlike the screen, textures have y pointing up
+                zoom: vec2(2.0 / size, 2.0 / size),
+                render_target: Some(target),
+                ..Default::default()
+            });
+            clear_background(Color::new(0.0, 0.0, 0.0, 0.0));
+            let light = (!self.light.is_empty()).then_some(&self.light[..]);
+            draw_map_region(map, texture, area, light);
+            self.dirty[page] = false;
+        }
+    }
+
+    /**
+     * Draws the baked pages in view, given in world pixels.
+     */
+    pub fn draw(&self, view: Rect) {
+        let size = PAGE_SIZE as f32 * TILE_SIZE;
+        for page in self.pages_in(view) {
+            if let Some(target) = self.pages[page] {
+                let area = self.page_area(page);
+                draw_texture_ex(
+                    target.texture,
+                    area.x * TILE_SIZE,
+                    area.y * TILE_SIZE,
+                    WHITE,
+                    DrawTextureParams {
+                        dest_size: Some(vec2(size, size)),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use macroquad::prelude::Vec2;
+
+use crate::{
+    components::{DoorState, Map, Tile},
+    level_utils::adjecent_idxs,
+};
+
+/**
+ * Cost of stepping onto a tile. Tiles without a cost can't be walked on.
+ */
+#[derive(Clone, Copy)]
+pub struct PathCosts {
+    pub floor: u32,
+    pub door: u32,
+    /// None makes locked doors impassable.
+    pub locked_door: Option<u32>,
+}
+
+impl Default for PathCosts {
+    fn default() -> PathCosts {
+        PathCosts {
+            floor: 1,
+            door: 3,
+            locked_door: None,
+        }
+    }
+}
+
+impl PathCosts {
+    pub fn cost(&self, tile: &Tile) -> Option<u32> {
+        match tile {
+            Tile::Floor | Tile::StairsUp | Tile::StairsDown => Some(self.floor),
+            Tile::Door(DoorState::Open | DoorState::Closed) => Some(self.door),
+            Tile::Door(DoorState::Locked(_)) => self.locked_door,
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Start and goal cells picked in the path viewer mode.
+ */
+pub struct PathTool {
+    pub from: Option<Vec2>,
+    pub to: Option<Vec2>,
+}
+
+impl PathTool {
+    pub fn new() -> PathTool {
+        PathTool {
+            from: None,
+            to: None,
+        }
+    }
+}
+
+fn manhattan(map: &Map, a: usize, b: usize) -> u32 {
+    let a = map.idx_to_vec2(a);
+    let b = map.idx_to_vec2(b);
+    ((a.x - b.x).abs() + (a.y - b.y).abs()) as u32
+}
+
+/**
+ * Returns the cheapest path from one index to another, both included, or None
+ * if the goal can't be reached. The start tile itself doesn't need to be
+ * walkable.
+ */
+pub fn astar(map: &Map, from: usize, to: usize, costs: &PathCosts) -> Option<Vec<usize>> {
+    costs.cost(&map.tiles[to])?;
+
+    let min_cost = costs
+        .floor
+        .min(costs.door)
+        .min(costs.locked_door.unwrap_or(u32::MAX));
+    let mut cost_so_far = vec![u32::MAX; map.tiles.len()];
+    let mut came_from = vec![usize::MAX; map.tiles.len()];
+    let mut open = BinaryHeap::new();
+
+    cost_so_far[from] = 0;
+    open.push(Reverse((manhattan(map, from, to) * min_cost, from)));
+
+    while let Some(Reverse((_, idx))) = open.pop() {
+        if idx == to {
+            let mut path = vec![to];
+            let mut current = to;
+            while current != from {
+                current = came_from[current];
+                path.push(current);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for adj in adjecent_idxs(map, idx) {
+            let step = match costs.cost(&map.tiles[adj]) {
+                Some(step) => step,
+                None => continue,
+            };
+            let cost = cost_so_far[idx] + step;
+            if cost < cost_so_far[adj] {
+                cost_so_far[adj] = cost;
+                came_from[adj] = idx;
+                open.push(Reverse((cost + manhattan(map, adj, to) * min_cost, adj)));
+            }
+        }
+    }
+
+    None
+}
+
+/**
+ * Returns the cost of reaching every tile from the closest of the sources, or
+ * None for tiles that can't be reached.
+ */
+pub fn dijkstra_map(map: &Map, sources: &[usize], costs: &PathCosts) -> Vec<Option<u32>> {
+    let mut distances = vec![None; map.tiles.len()];
+    let mut open = BinaryHeap::new();
+    for source in sources.iter() {
+        distances[*source] = Some(0);
+        open.push(Reverse((0, *source)));
+    }
+
+    while let Some(Reverse((distance, idx))) = open.pop() {
+        if distances[idx].is_some_and(|d| d < distance) {
+            continue;
+        }
+
+        for adj in adjecent_idxs(map, idx) {
+            let step = match costs.cost(&map.tiles[adj]) {
+                Some(step) => step,
+                None => continue,
+            };
+            let cost = distance + step;
+            if distances[adj].is_none_or(|d| cost < d) {
+                distances[adj] = Some(cost);
+                open.push(Reverse((cost, adj)));
+            }
+        }
+    }
+
+    distances
+}
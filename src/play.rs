@@ -0,0 +1,71 @@
+use macroquad::prelude::Vec2;
+
+use crate::{
+    components::{Map, RoomKind},
+    fov::{field_of_view, FOV_RADIUS},
+    level_utils::is_walkable,
+};
+
+/**
+ * A player walking through the map in play mode, along with what they have
+ * seen so far.
+ */
+pub struct Exploration {
+    pub player: Vec2,
+    /// Cells the player can see right now.
+    pub visible: Vec<bool>,
+    /// Cells the player has seen at some point.
+    pub seen: Vec<bool>,
+    /// Walkable cells on the map, and how many of them have been seen.
+    pub walkable: usize,
+    pub explored: usize,
+}
+
+impl Exploration {
+    /**
+     * Puts the player in the middle of the start room, or on the first
+     * walkable cell if there is none. None if nothing on the map is walkable.
+     */
+    pub fn start(map: &Map) -> Option<Exploration> {
+        let player = map
+            .rooms
+            .iter()
+            .find(|r| r.kind == RoomKind::Start)
+            .map(|r| r.center())
+            .or_else(|| {
+                let idx = map.tiles.iter().position(is_walkable)?;
+                Some(map.idx_to_vec2(idx))
+            })?;
+
+        let mut exploration = Exploration {
+            player,
+            visible: Vec::new(),
+            seen: vec![false; map.tiles.len()],
+            walkable: map.tiles.iter().filter(|t| is_walkable(t)).count(),
+            explored: 0,
+        };
+        exploration.look(map);
+        Some(exploration)
+    }
+
+    /**
+     * Moves the player one cell in dir, unless the way is blocked.
+     */
+    pub fn step(&mut self, map: &Map, dir: Vec2) {
+        let next = self.player + dir;
+        if map.in_bounds(next) && is_walkable(&map.tiles[map.idx(next)]) {
+            self.player = next;
+            self.look(map);
+        }
+    }
+
+    fn look(&mut self, map: &Map) {
+        self.visible = field_of_view(map, self.player, FOV_RADIUS);
+        for (idx, visible) in self.visible.iter().enumerate() {
+            if *visible && !self.seen[idx] {
+                self.seen[idx] = true;
+                self.explored += is_walkable(&map.tiles[idx]) as usize;
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+
+use crate::{
+    components::Map,
+    level_utils::{adjecent_idxs, is_walkable},
+};
+
+/**
+ * Which rooms are directly connected to each other, through doors and
+ * corridors that don't pass through a third room.
+ */
+pub struct RoomGraph {
+    pub edges: Vec<Vec<usize>>,
+}
+
+/**
+ * Returns the index of the room each tile belongs to, if any.
+ */
+pub fn room_idxs(map: &Map) -> Vec<Option<usize>> {
+    let mut rooms = vec![None; map.tiles.len()];
+    for (i, room) in map.rooms.iter().enumerate() {
+        for cell in room.cells() {
+            if map.in_bounds(cell) {
+                rooms[map.idx(cell)] = Some(i);
+            }
+        }
+    }
+
+    rooms
+}
+
+impl RoomGraph {
+    pub fn new(map: &Map) -> RoomGraph {
+        let room_of = room_idxs(map);
+        let mut edges = vec![Vec::new(); map.rooms.len()];
+
+        for (i, room) in map.rooms.iter().enumerate() {
+            let mut visited = vec![false; map.tiles.len()];
+            let mut queue = room
+                .cells()
+                .filter(|c| map.in_bounds(*c))
+                .map(|c| map.idx(c))
+                .collect::<VecDeque<usize>>();
+            queue.iter().for_each(|idx| visited[*idx] = true);
+
+            while let Some(idx) = queue.pop_front() {
+                for adj in adjecent_idxs(map, idx) {
+                    if visited[adj] || !is_walkable(&map.tiles[adj]) {
+                        continue;
+                    }
+                    visited[adj] = true;
+
+                    match room_of[adj] {
+                        Some(other) if other != i => {
+                            if !edges[i].contains(&other) {
+                                edges[i].push(other);
+                            }
+                        }
+                        _ => queue.push_back(adj),
+                    }
+                }
+            }
+        }
+
+        RoomGraph { edges }
+    }
+
+    /**
+     * Number of rooms passed on the shortest way from a room to every other
+     * room, or None for rooms that can't be reached. Also returns the room each
+     * room was reached from.
+     */
+    pub fn distances(&self, from: usize) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+        let mut distances = vec![None; self.edges.len()];
+        let mut parents = vec![None; self.edges.len()];
+        let mut queue = VecDeque::from([from]);
+        distances[from] = Some(0);
+
+        while let Some(room) = queue.pop_front() {
+            let distance = distances[room].unwrap_or(0);
+            for other in self.edges[room].iter() {
+                if distances[*other].is_none() {
+                    distances[*other] = Some(distance + 1);
+                    parents[*other] = Some(room);
+                    queue.push_back(*other);
+                }
+            }
+        }
+
+        (distances, parents)
+    }
+
+    /**
+     * Returns the two rooms that are furthest apart, out of the rooms allowed
+     * returns true for.
+     */
+    pub fn furthest_rooms(&self, allowed: impl Fn(usize) -> bool) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize, usize)> = None;
+        for from in (0..self.edges.len()).filter(|r| allowed(*r)) {
+            let (distances, _) = self.distances(from);
+            for (to, distance) in distances.iter().enumerate() {
+                if !allowed(to) {
+                    continue;
+                }
+                if let Some(distance) = distance {
+                    if best.is_none_or(|(_, _, d)| *distance > d) {
+                        best = Some((from, to, *distance));
+                    }
+                }
+            }
+        }
+
+        best.map(|(from, to, _)| (from, to))
+    }
+
+    /**
+     * Returns the rooms on the shortest way from one room to another, both
+     * included, or an empty Vec if they aren't connected.
+     */
+    pub fn path(&self, from: usize, to: usize) -> Vec<usize> {
+        let (distances, parents) = self.distances(from);
+        if distances[to].is_none() {
+            return Vec::new();
+        }
+
+        let mut path = vec![to];
+        let mut current = to;
+        while let Some(parent) = parents[current] {
+            path.push(parent);
+            current = parent;
+        }
+        path.reverse();
+        path
+    }
+}
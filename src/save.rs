@@ -0,0 +1,359 @@
+use std::fs;
+
+use macroquad::prelude::{vec2, Vec2};
+
+use crate::{
+    components::{
+        DoorState, Map, Prop, PropKind, Room, RoomKind, Spawn, SpawnKind, Tile, WallMaterial,
+    },
+    level_utils::refresh_draw_tiles,
+};
+
+pub const SAVE_PATH: &str = "dungeon.txt";
+/// Line between the rows of the map and the details written after them.
+const DETAILS: &str = "--";
+
+fn tile_to_char(tile: &Tile) -> char {
+    match tile {
+        Tile::Wall => '#',
+        Tile::Floor => '.',
+        Tile::Door(DoorState::Open) => '\'',
+        Tile::Door(DoorState::Closed) => '+',
+        Tile::Door(DoorState::Locked(id)) => (b'A' + id) as char,
+        Tile::Door(DoorState::Secret) => '=',
+        Tile::Dirt => ' ',
+        Tile::StairsUp => '<',
+        Tile::StairsDown => '>',
+    }
+}
+
+fn char_to_tile(c: char) -> Option<Tile> {
+    match c {
+        '#' => Some(Tile::Wall),
+        '.' => Some(Tile::Floor),
+        '\'' => Some(Tile::Door(DoorState::Open)),
+        '+' => Some(Tile::Door(DoorState::Closed)),
+        'A'..='Z' => Some(Tile::Door(DoorState::Locked(c as u8 - b'A'))),
+        '=' => Some(Tile::Door(DoorState::Secret)),
+        ' ' => Some(Tile::Dirt),
+        '<' => Some(Tile::StairsUp),
+        '>' => Some(Tile::StairsDown),
+        _ => None,
+    }
+}
+
+fn spawn_to_char(kind: &SpawnKind) -> char {
+    match kind {
+        SpawnKind::Monster => '&',
+        SpawnKind::Treasure => '$',
+        SpawnKind::Trap => '^',
+        SpawnKind::Key(id) => (b'a' + id) as char,
+    }
+}
+
+fn char_to_spawn(c: char) -> Option<SpawnKind> {
+    match c {
+        '&' => Some(SpawnKind::Monster),
+        '$' => Some(SpawnKind::Treasure),
+        '^' => Some(SpawnKind::Trap),
+        'a'..='z' => Some(SpawnKind::Key(c as u8 - b'a')),
+        _ => None,
+    }
+}
+
+fn material_to_char(material: &WallMaterial) -> char {
+    match material {
+        WallMaterial::Stone => 's',
+        WallMaterial::Brick => 'b',
+    }
+}
+
+fn char_to_material(c: char) -> Option<WallMaterial> {
+    match c {
+        's' => Some(WallMaterial::Stone),
+        'b' => Some(WallMaterial::Brick),
+        _ => None,
+    }
+}
+
+fn cell_to_string(map: &Map, idx: usize) -> String {
+    let pos = map.idx_to_vec2(idx);
+    format!("{} {}", pos.x, pos.y)
+}
+
+fn parse_number(word: &str) -> Result<usize, String> {
+    word.parse::<usize>()
+        .map_err(|_| format!("invalid number \"{}\"", word))
+}
+
+/**
+ * Writes everything about the map that isn't a tile or a spawn, one detail
+ * per line: the seed, the rooms with their kinds and shapes, the start, goal
+ * and critical path, the props and the wall materials row by row.
+ */
+fn details_to_string(map: &Map) -> String {
+    let mut lines = vec![format!("seed {}", map.seed)];
+    for room in map.rooms.iter() {
+        let mask = room
+            .mask
+            .iter()
+            .map(|m| if *m { '1' } else { '0' })
+            .collect::<String>();
+        let mut line = format!(
+            "room {} {} {} {} {} {}",
+            room.kind.name(),
+            room.pos.x,
+            room.pos.y,
+            room.size.x,
+            room.size.y,
+            mask
+        );
+        for entrance in room.entrances.iter().flatten() {
+            line.push_str(&format!(" {} {}", entrance.x, entrance.y));
+        }
+        lines.push(line);
+    }
+    if let Some(start) = map.start_room {
+        lines.push(format!("start {}", start));
+    }
+    if let Some(goal) = map.goal_room {
+        lines.push(format!("goal {}", goal));
+    }
+    if !map.critical_path.is_empty() {
+        let path = map.critical_path.iter().map(|r| r.to_string());
+        lines.push(format!("path {}", path.collect::<Vec<String>>().join(" ")));
+    }
+    for prop in map.props.iter() {
+        lines.push(format!(
+            "prop {} {} {}",
+            prop.kind.name(),
+            prop.kind.variant(),
+            cell_to_string(map, prop.pos)
+        ));
+    }
+    for row in map.materials.chunks(map.size.x as usize) {
+        lines.push(format!(
+            "materials {}",
+            row.iter().map(material_to_char).collect::<String>()
+        ));
+    }
+
+    lines.join("\n")
+}
+
+/**
+ * Reads one line written by details_to_string into map. materials is the row
+ * the next materials line is for.
+ */
+fn parse_detail(map: &mut Map, line: &str, materials: &mut usize) -> Result<(), String> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let cell = |x: &str, y: &str| -> Result<Vec2, String> {
+        let pos = vec2(parse_number(x)? as f32, parse_number(y)? as f32);
+        match map.in_bounds(pos) {
+            true => Ok(pos),
+            false => Err(format!("{},{} is outside the map", x, y)),
+        }
+    };
+    match words[..] {
+        ["seed", seed] => {
+            map.seed = seed
+                .parse()
+                .map_err(|_| format!("invalid seed \"{}\"", seed))?;
+        }
+        ["room", kind, x, y, w, h, mask, ref entrances @ ..] => {
+            let kind = RoomKind::ALL
+                .into_iter()
+                .find(|k| k.name() == kind)
+                .ok_or_else(|| format!("unknown room kind \"{}\"", kind))?;
+            let pos = cell(x, y)?;
+            let size = vec2(parse_number(w)? as f32, parse_number(h)? as f32);
+            let mask = mask.chars().map(|c| c == '1').collect::<Vec<bool>>();
+            if mask.len() != ((size.x + 1.0) * (size.y + 1.0)) as usize {
+                return Err(format!("room mask \"{}\" doesn't fit its size", line));
+            }
+            let mut room = Room::new(pos, size, mask);
+            room.kind = kind;
+            if !entrances.is_empty() {
+                room.entrances = Some(
+                    entrances
+                        .chunks(2)
+                        .map(|e| match e {
+                            [x, y] => Ok(vec2(parse_number(x)? as f32, parse_number(y)? as f32)),
+                            _ => Err(format!("entrance of \"{}\" is missing a y", line)),
+                        })
+                        .collect::<Result<Vec<Vec2>, String>>()?,
+                );
+            }
+            map.rooms.push(room);
+        }
+        ["start", room] => map.start_room = Some(parse_number(room)?),
+        ["goal", room] => map.goal_room = Some(parse_number(room)?),
+        ["path", ref rooms @ ..] => {
+            map.critical_path = rooms
+                .iter()
+                .map(|r| parse_number(r))
+                .collect::<Result<Vec<usize>, String>>()?;
+        }
+        ["prop", name, variant, x, y] => {
+            let variant = variant
+                .parse::<u8>()
+                .map_err(|_| format!("invalid prop variant \"{}\"", variant))?;
+            let kind = PropKind::from_name(name, variant)
+                .ok_or_else(|| format!("unknown prop \"{}\"", name))?;
+            let pos = map.idx(cell(x, y)?);
+            map.props.push(Prop { kind, pos });
+        }
+        ["materials", row] => {
+            if *materials >= map.size.y as usize {
+                return Err("more material rows than map rows".to_owned());
+            }
+            for (x, c) in row.chars().enumerate().take(map.size.x as usize) {
+                let idx = map.idx_xy(x, *materials);
+                map.materials[idx] =
+                    char_to_material(c).ok_or_else(|| format!("unknown wall material '{}'", c))?;
+            }
+            *materials += 1;
+        }
+        _ => return Err(format!("can't read \"{}\"", line)),
+    }
+
+    Ok(())
+}
+
+/**
+ * Serializes the map as one line of characters per row. Spawns are written in
+ * place of the floor they stand on, keys as the lowercase letter matching the
+ * uppercase letter of their locked door. The rooms, props and wall materials
+ * follow after a line of DETAILS.
+ */
+pub fn map_to_string(map: &Map) -> String {
+    let mut chars = map.tiles.iter().map(tile_to_char).collect::<Vec<char>>();
+    for spawn in map.spawns.iter() {
+        chars[spawn.pos] = spawn_to_char(&spawn.kind);
+    }
+
+    let mut out = String::new();
+    for row in chars.chunks(map.size.x as usize) {
+        out.extend(row.iter());
+        out.push('\n');
+    }
+    out.push_str(DETAILS);
+    out.push('\n');
+    out.push_str(&details_to_string(map));
+    out.push('\n');
+
+    out
+}
+
+/**
+ * Parses a map written by map_to_string. Short rows are padded with dirt. Maps
+ * without details, like hand-written ones, only get their tiles and spawns.
+ */
+pub fn map_from_str(s: &str) -> Result<Map, String> {
+    let (s, details) = match s.split_once(&format!("\n{}\n", DETAILS)) {
+        Some((rows, details)) => (rows, details),
+        None => (s, ""),
+    };
+    let rows = s.lines().collect::<Vec<&str>>();
+    let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+    if width == 0 {
+        return Err("map is empty".to_owned());
+    }
+
+    let mut map = Map::new(vec2(width as f32, rows.len() as f32));
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let idx = map.idx_xy(x, y);
+            if let Some(kind) = char_to_spawn(c) {
+                map.tiles[idx] = Tile::Floor;
+                map.spawns.push(Spawn { kind, pos: idx });
+                continue;
+            }
+
+            let tile =
+                char_to_tile(c).ok_or_else(|| format!("unknown tile '{}' at {},{}", c, x, y))?;
+            map.tiles[idx] = tile;
+        }
+    }
+    let mut materials = 0;
+    for (n, line) in details
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        parse_detail(&mut map, line, &mut materials)
+            .map_err(|e| format!("line {}: {}", rows.len() + n + 2, e))?;
+    }
+    let rooms = map.rooms.len();
+    let room_ids = map.start_room.iter().chain(map.goal_room.iter());
+    if room_ids
+        .chain(map.critical_path.iter())
+        .any(|r| *r >= rooms)
+    {
+        return Err("start, goal or path names a room that doesn't exist".to_owned());
+    }
+    refresh_draw_tiles(&mut map);
+
+    Ok(map)
+}
+
+pub fn save_map(map: &Map, path: &str) -> Result<(), String> {
+    fs::write(path, map_to_string(map)).map_err(|e| e.to_string())
+}
+
+pub fn load_map(path: &str) -> Result<Map, String> {
+    let s = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    map_from_str(&s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{generate_dungeon, GeneratorParams},
+        GAME_HEIGHT, GAME_WIDTH,
+    };
+
+    #[test]
+    fn saved_maps_load_the_same() {
+        let mut map = Map::new(vec2(GAME_WIDTH, GAME_HEIGHT));
+        generate_dungeon(&mut map, 7, &GeneratorParams::default(), None);
+        let loaded = map_from_str(&map_to_string(&map)).unwrap();
+
+        assert!(loaded.tiles == map.tiles);
+        // spawns are read back row by row
+        let mut spawns = map.spawns.clone();
+        spawns.sort_by_key(|s| s.pos);
+        assert!(loaded.spawns == spawns);
+        assert!(!map.props.is_empty() && loaded.props == map.props);
+        assert!(loaded.materials == map.materials);
+        assert_eq!(loaded.seed, map.seed);
+        assert_eq!(loaded.start_room, map.start_room);
+        assert_eq!(loaded.goal_room, map.goal_room);
+        assert_eq!(loaded.critical_path, map.critical_path);
+        assert_eq!(loaded.rooms.len(), map.rooms.len());
+        for (a, b) in loaded.rooms.iter().zip(map.rooms.iter()) {
+            assert!(a.kind == b.kind && a.pos == b.pos && a.size == b.size);
+            assert!(a.mask == b.mask && a.entrances == b.entrances);
+        }
+    }
+
+    #[test]
+    fn maps_without_details_keep_tiles_and_spawns() {
+        let map = map_from_str("#####\n#<.$#\n#####\n").unwrap();
+        assert_eq!(map.size, vec2(5.0, 3.0));
+        assert!(map.tiles[map.idx_xy(1, 1)] == Tile::StairsUp);
+        assert_eq!(map.spawns.len(), 1);
+        assert!(map.rooms.is_empty() && map.props.is_empty());
+    }
+
+    #[test]
+    fn broken_details_are_rejected() {
+        let rows = "#####\n#...#\n#####\n--\n";
+        assert!(map_from_str(&format!("{}room hall 1 1 2 0 111", rows)).is_err());
+        assert!(map_from_str(&format!("{}room normal 1 1 2 0 11", rows)).is_err());
+        assert!(map_from_str(&format!("{}prop chest 9 9", rows)).is_err());
+        assert!(map_from_str(&format!("{}start 0", rows)).is_err());
+        assert!(map_from_str(&format!("{}room normal 1 1 2 0 111\nstart 0", rows)).is_ok());
+    }
+}
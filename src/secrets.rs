@@ -0,0 +1,256 @@
+use macroquad::{
+    prelude::{vec2, Vec2},
+    rand,
+};
+
+use crate::{
+    components::{DoorState, Map, Room, RoomKind, Tile},
+    level_utils::{adjecent_idxs, find_tile, is_walkable, refresh_draw_tiles, surrounding_idxs},
+    pathfinding::{dijkstra_map, PathCosts},
+};
+
+/// Sizes of secret rooms, counted like other rooms without their far edge.
+const SECRET_ROOM_SIZES: [Vec2; 4] = [
+    vec2(2.0, 2.0),
+    vec2(3.0, 2.0),
+    vec2(2.0, 3.0),
+    vec2(3.0, 3.0),
+];
+
+/**
+ * A spot for a secret room: where it goes, the wall cell that becomes its
+ * door and how good a spot it is.
+ */
+struct Spot {
+    pos: Vec2,
+    door: usize,
+    score: u32,
+}
+
+/**
+ * How well a secret door leading out onto idx hides. Dead ends beat other
+ * corridor cells, which beat room floors.
+ */
+fn score(map: &Map, idx: usize) -> u32 {
+    let pos = map.idx_to_vec2(idx);
+    if map.rooms.iter().any(|r| r.contains(pos)) {
+        return 0;
+    }
+    let ways = adjecent_idxs(map, idx)
+        .iter()
+        .filter(|a| is_walkable(&map.tiles[**a]))
+        .count();
+    if ways <= 1 {
+        2
+    } else {
+        1
+    }
+}
+
+/**
+ * Every place a secret room of size fits. The room has to be dug out of
+ * untouched ground, and its walls may only share cells with walls already
+ * there. The door goes in the middle of one of those shared walls, with a
+ * cell that can be reached on the other side and no other door next to it.
+ */
+fn spots(map: &Map, size: Vec2, reached: &[Option<u32>]) -> Vec<Spot> {
+    let (w, h) = (size.x as i32, size.y as i32);
+    let mut spots = Vec::new();
+    for y in 1..map.size.y as i32 - h - 1 {
+        for x in 1..map.size.x as i32 - w - 1 {
+            let tile =
+                |dx: i32, dy: i32| map.tiles[map.idx_xy((x + dx) as usize, (y + dy) as usize)];
+            let inside = (0..=h).all(|dy| (0..=w).all(|dx| tile(dx, dy) == Tile::Dirt));
+            let ring = (-1..=h + 1)
+                .flat_map(|dy| (-1..=w + 1).map(move |dx| (dx, dy)))
+                .filter(|(dx, dy)| *dx < 0 || *dy < 0 || *dx > w || *dy > h)
+                .collect::<Vec<(i32, i32)>>();
+            if !inside
+                || !ring
+                    .iter()
+                    .all(|(dx, dy)| matches!(tile(*dx, *dy), Tile::Dirt | Tile::Wall))
+            {
+                continue;
+            }
+
+            for (dx, dy) in ring {
+                let corner = (dx < 0 || dx > w) && (dy < 0 || dy > h);
+                if corner || tile(dx, dy) != Tile::Wall {
+                    continue;
+                }
+                // one step further out from the room
+                let out = vec2(
+                    (x + dx + (dx > w) as i32 - (dx < 0) as i32) as f32,
+                    (y + dy + (dy > h) as i32 - (dy < 0) as i32) as f32,
+                );
+                if !map.in_bounds(out) || reached[map.idx(out)].is_none() {
+                    continue;
+                }
+                let door = map.idx_xy((x + dx) as usize, (y + dy) as usize);
+                let crowded = surrounding_idxs(map, door)
+                    .iter()
+                    .any(|s| matches!(map.tiles[*s], Tile::Door(_)));
+                if !crowded {
+                    spots.push(Spot {
+                        pos: vec2(x as f32, y as f32),
+                        door,
+                        score: score(map, map.idx(out)),
+                    });
+                }
+            }
+        }
+    }
+
+    spots
+}
+
+/**
+ * Adds up to count small rooms that can only be entered through a secret
+ * door, built into the ground between the rest of the dungeon. Spots next to
+ * dead ends are picked first, then other corridors, then rooms.
+ */
+pub fn place_secret_rooms(map: &mut Map, count: usize) {
+    let start = match find_tile(map, Tile::StairsUp) {
+        Some(start) => start,
+        None => return,
+    };
+    // keys are always within reach, so locked doors don't count
+    let reached = dijkstra_map(
+        map,
+        &[start],
+        &PathCosts {
+            locked_door: Some(1),
+            ..PathCosts::default()
+        },
+    );
+
+    for _ in 0..count {
+        let size = SECRET_ROOM_SIZES[rand::gen_range(0, SECRET_ROOM_SIZES.len())];
+        let spots = spots(map, size, &reached);
+        let best = match spots.iter().map(|s| s.score).max() {
+            Some(best) => best,
+            None => continue,
+        };
+        let best = spots
+            .into_iter()
+            .filter(|s| s.score == best)
+            .collect::<Vec<Spot>>();
+        let spot = &best[rand::gen_range(0, best.len())];
+
+        let len = ((size.x + 1.0) * (size.y + 1.0)) as usize;
+        let mut room = Room::new(spot.pos, size, vec![true; len]);
+        room.kind = RoomKind::Secret;
+        let cells = room.cells().map(|c| map.idx(c)).collect::<Vec<usize>>();
+        for idx in cells.iter() {
+            map.tiles[*idx] = Tile::Floor;
+        }
+        for idx in cells.iter() {
+            for s in surrounding_idxs(map, *idx) {
+                if map.tiles[s] == Tile::Dirt {
+                    map.tiles[s] = Tile::Wall;
+                }
+            }
+        }
+        map.tiles[spot.door] = Tile::Door(DoorState::Secret);
+        map.rooms.push(room);
+    }
+}
+
+/**
+ * A copy of the map as the player sees it from above, without any sign of the
+ * secret rooms. Their floors and the walls only they needed go back to dirt,
+ * their props and spawns are dropped and their doors are plain walls.
+ */
+pub fn hide_secrets(map: &Map) -> Map {
+    let mut hidden = map.clone();
+    let mut secret = vec![false; map.tiles.len()];
+    for room in map.rooms.iter().filter(|r| r.kind == RoomKind::Secret) {
+        for cell in room.cells().filter(|c| map.in_bounds(*c)) {
+            secret[map.idx(cell)] = true;
+        }
+    }
+    if !secret.contains(&true) {
+        return hidden;
+    }
+
+    for (idx, tile) in hidden.tiles.iter_mut().enumerate() {
+        if secret[idx] {
+            *tile = Tile::Dirt;
+        } else if *tile == Tile::Door(DoorState::Secret) {
+            *tile = Tile::Wall;
+        }
+    }
+    let walls = (0..map.tiles.len())
+        .filter(|idx| secret[*idx])
+        .flat_map(|idx| surrounding_idxs(map, idx))
+        .filter(|s| hidden.tiles[*s] == Tile::Wall)
+        .collect::<Vec<usize>>();
+    for wall in walls {
+        let needed = surrounding_idxs(&hidden, wall)
+            .iter()
+            .any(|s| !matches!(hidden.tiles[*s], Tile::Wall | Tile::Dirt));
+        if !needed {
+            hidden.tiles[wall] = Tile::Dirt;
+        }
+    }
+    hidden.props.retain(|p| !secret[p.pos]);
+    hidden.spawns.retain(|s| !secret[s.pos]);
+    hidden.rooms.retain(|r| r.kind != RoomKind::Secret);
+    refresh_draw_tiles(&mut hidden);
+
+    hidden
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        level::{generate_dungeon, GeneratorParams},
+        GAME_HEIGHT, GAME_WIDTH,
+    };
+
+    fn generate(seed: u64, secret_rooms: usize) -> Map {
+        let params = GeneratorParams {
+            secret_rooms,
+            ..GeneratorParams::default()
+        };
+        let mut map = Map::new(vec2(GAME_WIDTH, GAME_HEIGHT));
+        generate_dungeon(&mut map, seed, &params, None);
+        map
+    }
+
+    #[test]
+    fn hidden_secret_rooms_look_like_they_were_never_dug() {
+        for seed in 0..40 {
+            let without = generate(seed, 0);
+            let hidden = hide_secrets(&generate(seed, 2));
+            assert!(hidden.tiles == without.tiles, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn hidden_secret_rooms_leave_nothing_behind() {
+        for seed in 0..40 {
+            let map = generate(seed, 2);
+            let hidden = hide_secrets(&map);
+            let secret = map
+                .rooms
+                .iter()
+                .filter(|r| r.kind == RoomKind::Secret)
+                .flat_map(|r| r.cells())
+                .map(|c| map.idx(c))
+                .collect::<Vec<usize>>();
+            assert!(!hidden.tiles.contains(&Tile::Door(DoorState::Secret)));
+            assert!(secret.iter().all(|idx| hidden.tiles[*idx] == Tile::Dirt));
+            assert!(hidden.props.iter().all(|p| !secret.contains(&p.pos)));
+            assert!(hidden.spawns.iter().all(|s| !secret.contains(&s.pos)));
+        }
+    }
+
+    #[test]
+    fn maps_without_secret_rooms_stay_the_same() {
+        let map = generate(3, 0);
+        let hidden = hide_secrets(&map);
+        assert!(hidden.tiles == map.tiles && hidden.props == map.props);
+    }
+}
@@ -0,0 +1,111 @@
+use macroquad::{
+    prelude::{vec2, Vec2},
+    rand,
+};
+
+static PREFAB_SHAPES: &str = include_str!("../assets/room_shapes.txt");
+
+/**
+ * Outline of a room within its bounding box.
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum RoomShape {
+    Rectangle,
+    Circle,
+    Cross,
+    LShape,
+    Pillars,
+    Prefab,
+}
+
+impl RoomShape {
+    pub const ALL: [RoomShape; 6] = [
+        RoomShape::Rectangle,
+        RoomShape::Circle,
+        RoomShape::Cross,
+        RoomShape::LShape,
+        RoomShape::Pillars,
+        RoomShape::Prefab,
+    ];
+}
+
+/**
+ * Builds the mask of a shape that fills a room of the given size. The mask has
+ * one entry per cell, row by row, and like rooms themselves covers size + 1
+ * cells in each direction. Prefab shapes bring their own size, so they are
+ * built from the prefab file instead.
+ */
+pub fn shape_mask(shape: RoomShape, size: Vec2) -> Vec<bool> {
+    let (w, h) = (size.x as i32 + 1, size.y as i32 + 1);
+    // pick the quadrant the L-shape is missing up front, so the rand calls
+    // don't depend on the size of the room
+    let (cut_x, cut_y) = match shape {
+        RoomShape::LShape => (rand::gen_range(0, 2) == 0, rand::gen_range(0, 2) == 0),
+        _ => (false, false),
+    };
+
+    (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| match shape {
+            RoomShape::Rectangle | RoomShape::Prefab => true,
+            RoomShape::Circle => {
+                let dx = (x as f32 - (w - 1) as f32 / 2.0) / (w as f32 / 2.0);
+                let dy = (y as f32 - (h - 1) as f32 / 2.0) / (h as f32 / 2.0);
+                dx * dx + dy * dy <= 1.0
+            }
+            RoomShape::Cross => {
+                let in_band = |v: i32, len: i32| v >= len / 3 && v < len - len / 3;
+                in_band(x, w) || in_band(y, h)
+            }
+            RoomShape::LShape => !((x >= w / 2) == cut_x && (y >= h / 2) == cut_y),
+            RoomShape::Pillars => {
+                let inside = x > 0 && x < w - 1 && y > 0 && y < h - 1;
+                !(inside && x % 2 == 1 && y % 2 == 1)
+            }
+        })
+        .collect()
+}
+
+/**
+ * Parses the prefab room shapes into their sizes and masks. Shapes are
+ * separated by blank lines, shorter rows are padded with cells that are left
+ * out of the room.
+ */
+pub fn parse_prefabs(text: &str) -> Vec<(Vec2, Vec<bool>)> {
+    let mut prefabs = Vec::new();
+    let mut rows: Vec<&str> = Vec::new();
+    let lines = text
+        .lines()
+        .map(|l| l.trim_end())
+        .filter(|l| !l.starts_with("//"))
+        .chain(std::iter::once(""));
+
+    for line in lines {
+        if !line.is_empty() {
+            rows.push(line);
+            continue;
+        }
+        if rows.is_empty() {
+            continue;
+        }
+
+        let w = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let mask = rows
+            .iter()
+            .flat_map(|r| (0..w).map(move |x| r.as_bytes().get(x) == Some(&b'.')))
+            .collect::<Vec<bool>>();
+        prefabs.push((vec2(w as f32 - 1.0, rows.len() as f32 - 1.0), mask));
+        rows.clear();
+    }
+
+    prefabs
+}
+
+/**
+ * Picks a random prefab shape.
+ */
+pub fn random_prefab() -> (Vec2, Vec<bool>) {
+    let mut prefabs = parse_prefabs(PREFAB_SHAPES);
+    let i = rand::gen_range(0, prefabs.len());
+    prefabs.swap_remove(i)
+}
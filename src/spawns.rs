@@ -0,0 +1,89 @@
+use macroquad::rand::{self, ChooseRandom};
+
+use crate::{
+    components::{Map, RoomKind, Spawn, SpawnKind, Tile},
+    level_utils::find_tile,
+    pathfinding::{dijkstra_map, PathCosts},
+};
+
+/**
+ * What can spawn in a room of the given kind, with relative weights.
+ */
+pub fn spawn_table(kind: RoomKind) -> &'static [(SpawnKind, u32)] {
+    match kind {
+        RoomKind::Normal => &[
+            (SpawnKind::Monster, 6),
+            (SpawnKind::Treasure, 1),
+            (SpawnKind::Trap, 3),
+        ],
+        RoomKind::Goal => &[(SpawnKind::Monster, 8), (SpawnKind::Treasure, 2)],
+        RoomKind::Treasure => &[(SpawnKind::Treasure, 6), (SpawnKind::Trap, 3)],
+        RoomKind::Boss => &[(SpawnKind::Monster, 1)],
+        RoomKind::Library => &[(SpawnKind::Monster, 3), (SpawnKind::Treasure, 1)],
+        RoomKind::Prison => &[(SpawnKind::Monster, 5), (SpawnKind::Trap, 2)],
+        RoomKind::Closet => &[(SpawnKind::Treasure, 1), (SpawnKind::Trap, 1)],
+        RoomKind::Start | RoomKind::Vault | RoomKind::Secret => &[],
+    }
+}
+
+fn pick_weighted(table: &[(SpawnKind, u32)]) -> Option<SpawnKind> {
+    let total = table.iter().map(|(_, weight)| weight).sum::<u32>();
+    if total == 0 {
+        return None;
+    }
+
+    let mut roll = rand::gen_range(0, total);
+    for (kind, weight) in table.iter() {
+        if roll < *weight {
+            return Some(*kind);
+        }
+        roll -= weight;
+    }
+    None
+}
+
+/**
+ * Fills the rooms with spawns picked from their room's spawn table. About
+ * density of the free floor tiles in each room get a spawn, but never a tile
+ * closer than min_distance steps to the up stairs. Corridors, doors, stairs and
+ * props stay empty.
+ */
+pub fn place_spawns(map: &mut Map, density: f32, min_distance: u32) {
+    let start = match find_tile(map, Tile::StairsUp) {
+        Some(start) => start,
+        None => return,
+    };
+    // keys are always within reach, so rooms behind locked doors get spawns too
+    let distances = dijkstra_map(
+        map,
+        &[start],
+        &PathCosts {
+            door: 1,
+            locked_door: Some(1),
+            ..PathCosts::default()
+        },
+    );
+
+    for room in map.rooms.clone().iter() {
+        let table = spawn_table(room.kind);
+        let mut spots = room
+            .cells()
+            .filter(|c| map.in_bounds(*c))
+            .map(|c| map.idx(c))
+            .filter(|idx| {
+                map.tiles[*idx] == Tile::Floor
+                    && map.spawn_at(*idx).is_none()
+                    && map.prop_at(*idx).is_none()
+                    && distances[*idx].is_some_and(|d| d >= min_distance)
+            })
+            .collect::<Vec<usize>>();
+        spots.shuffle();
+
+        let amount = (spots.len() as f32 * density).round() as usize;
+        for pos in spots.into_iter().take(amount) {
+            if let Some(kind) = pick_weighted(table) {
+                map.spawns.push(Spawn { kind, pos });
+            }
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use macroquad::{
+    file::load_string,
+    prelude::{vec2, Rect, Vec2},
+    texture::{load_texture, Texture2D},
+};
+
+use crate::{
+    components::{DoorState, Orientation, PropKind, SpawnKind, Tile, WallMaterial},
+    level_utils::{door_orientation, is_wall, mix},
+};
+
+static DEFAULT_TILESET: &str = include_str!("../assets/dungeon.tileset");
+static TILESET: OnceLock<Tileset> = OnceLock::new();
+
+pub const TILESET_PATH: &str = "assets/dungeon.tileset";
+
+/**
+ * Where the art of every tile sits in an atlas image.
+ */
+pub struct Tileset {
+    pub image: String,
+    /// Width and height of an atlas cell in texels.
+    pub tile_size: f32,
+    /// Weighted variants of every tile but walls, by tile name.
+    tiles: HashMap<String, Vec<(Vec2, u32)>>,
+    /// Atlas cell of every spawn, by spawn name.
+    spawns: HashMap<String, Vec2>,
+    /// Atlas cells of the parts or variants of every prop, by prop name.
+    props: HashMap<String, Vec<Vec2>>,
+    /// Atlas cell of a wall for every wall mask.
+    walls: [Vec2; 256],
+    /// How far the wall cells of a material are moved in the atlas, by
+    /// material name.
+    materials: HashMap<String, Vec2>,
+}
+
+/**
+ * Name of a tile in the tileset file. Doors have one per orientation, walls
+ * and secret doors have their own rules.
+ */
+pub fn tile_key(tile: &Tile, orientation: Orientation) -> &'static str {
+    let horizontal = orientation == Orientation::Horizontal;
+    match tile {
+        Tile::Wall | Tile::Door(DoorState::Secret) => "wall",
+        Tile::Floor => "floor",
+        Tile::Door(DoorState::Open) if horizontal => "open_door_horizontal",
+        Tile::Door(DoorState::Open) => "open_door_vertical",
+        Tile::Door(DoorState::Closed) if horizontal => "door_horizontal",
+        Tile::Door(DoorState::Closed) => "door_vertical",
+        Tile::Door(DoorState::Locked(_)) if horizontal => "locked_door_horizontal",
+        Tile::Door(DoorState::Locked(_)) => "locked_door_vertical",
+        Tile::Dirt => "dirt",
+        Tile::StairsUp => "stairs_up",
+        Tile::StairsDown => "stairs_down",
+    }
+}
+
+const TILE_KEYS: [&str; 11] = [
+    "empty",
+    "floor",
+    "door_horizontal",
+    "door_vertical",
+    "open_door_horizontal",
+    "open_door_vertical",
+    "locked_door_horizontal",
+    "locked_door_vertical",
+    "dirt",
+    "stairs_up",
+    "stairs_down",
+];
+
+/**
+ * Name of a spawn in the tileset file.
+ */
+fn spawn_key(kind: &SpawnKind) -> &'static str {
+    match kind {
+        SpawnKind::Monster => "monster",
+        SpawnKind::Treasure => "treasure",
+        SpawnKind::Trap => "trap",
+        SpawnKind::Key(_) => "key",
+    }
+}
+
+const SPAWN_KEYS: [&str; 4] = ["monster", "treasure", "trap", "key"];
+
+fn parse_cell(x: &str, y: &str) -> Result<Vec2, String> {
+    match (x.parse::<u32>(), y.parse::<u32>()) {
+        (Ok(x), Ok(y)) => Ok(vec2(x as f32, y as f32)),
+        _ => Err(format!("invalid atlas cell \"{} {}\"", x, y)),
+    }
+}
+
+/**
+ * Parses a wall rule pattern into the neighbours it cares about and the walls
+ * it expects among them, both as masks like wall_mask.
+ */
+fn parse_wall_pattern(pattern: &str) -> Result<(u8, u8), String> {
+    let cells = pattern.chars().filter(|c| *c != ' ').collect::<Vec<char>>();
+    if cells.len() != 9 {
+        return Err(format!("wall pattern \"{}\" needs 3x3 cells", pattern));
+    }
+
+    let mut care = 0;
+    let mut walls = 0;
+    for (bit, c) in cells[..4].iter().chain(cells[5..].iter()).enumerate() {
+        match c {
+            '#' => {
+                care |= 1 << bit;
+                walls |= 1 << bit;
+            }
+            '.' => care |= 1 << bit,
+            '?' => (),
+            c => {
+                return Err(format!(
+                    "unknown cell '{}' in wall pattern \"{}\"",
+                    c, pattern
+                ))
+            }
+        }
+    }
+
+    Ok((care, walls))
+}
+
+/**
+ * Parses a tileset file. Every line is one of "image <path>",
+ * "tile_size <texels>", "tile <name> <x> <y> [weight]",
+ * "wall <pattern> <x> <y>", "wall_material <name> <x> <y>",
+ * "spawn <name> <x> <y>" or "prop <name> <x> <y>", see
+ * assets/dungeon.tileset.
+ */
+pub fn parse_tileset(text: &str) -> Result<Tileset, String> {
+    let mut image = None;
+    let mut tile_size = None;
+    let mut tiles: HashMap<String, Vec<(Vec2, u32)>> = HashMap::new();
+    let mut rules: Vec<(u8, u8, Vec2)> = Vec::new();
+    let mut materials = HashMap::new();
+    let mut spawns = HashMap::new();
+    let mut props: HashMap<String, Vec<Vec2>> = HashMap::new();
+
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let error = |e: String| format!("line {}: {}", n, e);
+        match words[..] {
+            ["image", path] => image = Some(path.to_string()),
+            ["tile_size", size] => match size.parse::<f32>() {
+                Ok(size) if size > 0.0 => tile_size = Some(size),
+                _ => return Err(error(format!("invalid tile size \"{}\"", size))),
+            },
+            ["tile", name, x, y, ref weight @ ..] if weight.len() <= 1 => {
+                if !TILE_KEYS.contains(&name) {
+                    return Err(error(format!("unknown tile \"{}\"", name)));
+                }
+                let weight = match weight.first() {
+                    Some(w) => w
+                        .parse::<u32>()
+                        .map_err(|_| error(format!("invalid weight \"{}\"", w)))?,
+                    None => 1,
+                };
+                let cell = parse_cell(x, y).map_err(error)?;
+                tiles
+                    .entry(name.to_string())
+                    .or_default()
+                    .push((cell, weight));
+            }
+            ["wall", top, middle, bottom, x, y] => {
+                let pattern = format!("{} {} {}", top, middle, bottom);
+                let (care, walls) = parse_wall_pattern(&pattern).map_err(error)?;
+                rules.push((care, walls, parse_cell(x, y).map_err(error)?));
+            }
+            ["wall_material", name, x, y] => {
+                if !WallMaterial::ALL.iter().any(|m| m.name() == name) {
+                    return Err(error(format!("unknown wall material \"{}\"", name)));
+                }
+                materials.insert(name.to_string(), parse_cell(x, y).map_err(error)?);
+            }
+            ["spawn", name, x, y] => {
+                if !SPAWN_KEYS.contains(&name) {
+                    return Err(error(format!("unknown spawn \"{}\"", name)));
+                }
+                spawns.insert(name.to_string(), parse_cell(x, y).map_err(error)?);
+            }
+            ["prop", name, x, y] => {
+                if !PropKind::NAMES.contains(&name) {
+                    return Err(error(format!("unknown prop \"{}\"", name)));
+                }
+                let cell = parse_cell(x, y).map_err(error)?;
+                props.entry(name.to_string()).or_default().push(cell);
+            }
+            _ => return Err(error(format!("can't read \"{}\"", line))),
+        }
+    }
+
+    for key in TILE_KEYS {
+        let total = tiles.get(key).map_or(0, |v| v.iter().map(|(_, w)| w).sum());
+        if total == 0 {
+            return Err(format!("tile {} is missing", key));
+        }
+    }
+    if let Some(key) = SPAWN_KEYS.iter().find(|k| !spawns.contains_key(**k)) {
+        return Err(format!("spawn {} is missing", key));
+    }
+    if let Some(key) = PropKind::NAMES.iter().find(|k| !props.contains_key(**k)) {
+        return Err(format!("prop {} is missing", key));
+    }
+
+    let mut walls = [Vec2::ZERO; 256];
+    for (mask, cell) in walls.iter_mut().enumerate() {
+        *cell = rules
+            .iter()
+            .find(|(care, walls, _)| mask as u8 & care == *walls)
+            .map(|(_, _, cell)| *cell)
+            .ok_or_else(|| format!("no wall rule matches the neighbours {:08b}", mask))?;
+    }
+
+    Ok(Tileset {
+        image: image.ok_or("image is missing")?,
+        tile_size: tile_size.ok_or("tile_size is missing")?,
+        tiles,
+        spawns,
+        props,
+        walls,
+        materials,
+    })
+}
+
+impl Tileset {
+    /**
+     * Atlas cell of a tile. Walls and secret doors are looked up by the mask
+     * of the walls around them and moved to the cells of their material.
+     * Doors use the cells of the way the walls around them run, horizontal if
+     * they don't frame the door. Other tiles pick one of their variants based
+     * on variant, so the same cell always looks the same.
+     */
+    pub fn atlas_pos(
+        &self,
+        tile: &Tile,
+        wall_mask: u8,
+        material: WallMaterial,
+        variant: u64,
+    ) -> Vec2 {
+        if is_wall(tile) {
+            let offset = self
+                .materials
+                .get(material.name())
+                .copied()
+                .unwrap_or(Vec2::ZERO);
+            return self.walls[wall_mask as usize] + offset;
+        }
+
+        let orientation = door_orientation(wall_mask).unwrap_or(Orientation::Horizontal);
+        let variants = &self.tiles[tile_key(tile, orientation)];
+        let total = variants.iter().map(|(_, w)| *w as u64).sum::<u64>();
+        let mut roll = mix(variant) % total;
+        for (cell, weight) in variants.iter() {
+            if roll < *weight as u64 {
+                return *cell;
+            }
+            roll -= *weight as u64;
+        }
+        variants[0].0
+    }
+
+    /**
+     * Atlas cell drawn for cells whose draw tiles haven't been worked out.
+     */
+    pub fn empty(&self) -> Vec2 {
+        self.tiles["empty"][0].0
+    }
+
+    /**
+     * Atlas cell of a spawn.
+     */
+    pub fn spawn_pos(&self, kind: &SpawnKind) -> Vec2 {
+        self.spawns[spawn_key(kind)]
+    }
+
+    /**
+     * Atlas cell of a prop. Props with more parts or variants than the
+     * tileset lists start over from the first one.
+     */
+    pub fn prop_pos(&self, kind: &PropKind) -> Vec2 {
+        let cells = &self.props[kind.name()];
+        cells[kind.variant() as usize % cells.len()]
+    }
+
+    /**
+     * The part of the atlas image covered by a cell.
+     */
+    pub fn source(&self, cell: Vec2) -> Rect {
+        Rect::new(
+            cell.x * self.tile_size,
+            cell.y * self.tile_size,
+            self.tile_size,
+            self.tile_size,
+        )
+    }
+}
+
+/**
+ * Loads the tileset file at path along with its image and makes it the
+ * tileset used for drawing. Has to happen before anything is drawn, the
+ * tileset can't change afterwards.
+ */
+pub async fn load_tileset(path: &str) -> Result<(&'static Tileset, Texture2D), String> {
+    let text = load_string(path).await.map_err(|e| e.to_string())?;
+    let parsed = parse_tileset(&text)?;
+    let texture = load_texture(&parsed.image)
+        .await
+        .map_err(|e| format!("{}: {}", parsed.image, e))?;
+    TILESET
+        .set(parsed)
+        .map_err(|_| "a tileset is already in use".to_string())?;
+    Ok((tileset(), texture))
+}
+
+/**
+ * The tileset used for drawing. Falls back to the tileset built into the
+ * binary if none was loaded.
+ */
+pub fn tileset() -> &'static Tileset {
+    TILESET.get_or_init(|| {
+        parse_tileset(DEFAULT_TILESET).unwrap_or_else(|e| panic!("invalid built-in tileset: {}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * The atlas cell assets/dungeon.tileset should give a wall with the given
+     * mask, worked out from its sides and double walls.
+     */
+    fn expected_wall(mask: u8) -> Vec2 {
+        let wall = |bit: u8| mask & 1 << bit != 0;
+        let (nw, n, ne, w, e, sw, s, se) = (
+            wall(0),
+            wall(1),
+            wall(2),
+            wall(3),
+            wall(4),
+            wall(5),
+            wall(6),
+            wall(7),
+        );
+        let (x, y) = match (n, w, e, s) {
+            (false, true, true, true) if sw && se => (3, 0),
+            (true, true, true, false) if nw && ne => (3, 0),
+            (true, false, true, true) if ne && se => (4, 1),
+            (true, true, false, true) if nw && sw => (4, 1),
+            (false, false, false, false) => (1, 2),
+            (false, true, false, false) => (3, 1),
+            (false, false, true, false) => (2, 2),
+            (false, false, false, true) => (1, 2),
+            (true, false, false, false) => (0, 2),
+            (true, true, true, true) => (5, 0),
+            (true, false, false, true) => (4, 1),
+            (false, true, true, false) => (3, 0),
+            (false, true, true, true) => (0, 0),
+            (true, true, true, false) => (1, 1),
+            (true, true, false, true) => (1, 0),
+            (true, false, true, true) => (0, 1),
+            (false, false, true, true) => (2, 0),
+            (false, true, false, true) => (4, 0),
+            (true, false, true, false) => (2, 1),
+            (true, true, false, false) => (4, 2),
+        };
+        vec2(x as f32, y as f32)
+    }
+
+    #[test]
+    fn every_wall_mask_has_its_cell() {
+        let tileset = parse_tileset(DEFAULT_TILESET).unwrap();
+        for mask in 0..=255u8 {
+            assert_eq!(
+                tileset.walls[mask as usize],
+                expected_wall(mask),
+                "mask {:08b}",
+                mask
+            );
+        }
+    }
+
+    #[test]
+    fn isolated_walls_ignore_their_corners() {
+        let tileset = parse_tileset(DEFAULT_TILESET).unwrap();
+        // no walls at all, and walls only in the four corners
+        assert_eq!(tileset.walls[0b0000_0000], vec2(1.0, 2.0));
+        assert_eq!(tileset.walls[0b1010_0101], vec2(1.0, 2.0));
+    }
+
+    #[test]
+    fn double_walls_are_drawn_as_lines() {
+        let tileset = parse_tileset(DEFAULT_TILESET).unwrap();
+        // horizontal with a full row of walls below or above
+        assert_eq!(tileset.walls[0b1111_1000], vec2(3.0, 0.0));
+        assert_eq!(tileset.walls[0b0001_1111], vec2(3.0, 0.0));
+        // vertical with a full column of walls right or left
+        assert_eq!(tileset.walls[0b1101_0110], vec2(4.0, 1.0));
+        assert_eq!(tileset.walls[0b0110_1011], vec2(4.0, 1.0));
+        // a missing corner makes it a T piece again
+        assert_eq!(tileset.walls[0b0111_1000], vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn props_and_spawns_come_from_the_file() {
+        let tileset = parse_tileset(DEFAULT_TILESET).unwrap();
+        assert_eq!(tileset.spawn_pos(&SpawnKind::Key(3)), vec2(5.0, 6.0));
+        assert_eq!(tileset.prop_pos(&PropKind::Table(2)), vec2(2.0, 9.0));
+        // chairs have two variants, so the third is the first again
+        assert_eq!(
+            tileset.prop_pos(&PropKind::Chair(2)),
+            tileset.prop_pos(&PropKind::Chair(0))
+        );
+    }
+
+    #[test]
+    fn missing_props_and_spawns_are_rejected() {
+        let text = DEFAULT_TILESET.replace("prop wall_torch 5 6", "");
+        assert!(parse_tileset(&text).is_err());
+        let text = DEFAULT_TILESET.replace("spawn trap 0 6", "");
+        assert!(parse_tileset(&text).is_err());
+    }
+
+    #[test]
+    fn uncovered_masks_are_rejected() {
+        let text = DEFAULT_TILESET.replace("wall ?.? .x. ?.? 1 2", "");
+        assert!(parse_tileset(&text).is_err());
+    }
+}
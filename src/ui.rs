@@ -0,0 +1,144 @@
+use macroquad::{
+    hash,
+    prelude::vec2,
+    ui::{root_ui, widgets},
+    window::screen_width,
+};
+
+use crate::{
+    components::World,
+    level::{self, Generator, RoomShapes, RoomSizes},
+};
+
+const PANEL_WIDTH: f32 = 300.0;
+const PANEL_HEIGHT: f32 = 405.0;
+
+pub struct Panel {
+    pub open: bool,
+    pub seed_text: String,
+}
+
+impl Panel {
+    pub fn new() -> Panel {
+        Panel {
+            open: false,
+            seed_text: String::new(),
+        }
+    }
+}
+
+/**
+ * Draws the generator parameter panel and regenerates the map when asked to.
+ * Leaving the seed empty picks a new random seed.
+ */
+pub fn draw_panel(w: &mut World) {
+    if !w.panel.open {
+        return;
+    }
+
+    let mut params = w.params;
+    let mut seed_text = w.panel.seed_text.clone();
+    let mut regenerate = false;
+    let current_seed = w.seed;
+
+    widgets::Window::new(
+        hash!(),
+        vec2(screen_width() - PANEL_WIDTH - 8.0, 8.0),
+        vec2(PANEL_WIDTH, PANEL_HEIGHT),
+    )
+    .label("Generator")
+    .ui(&mut root_ui(), |ui| {
+        let generators = Generator::ALL.map(|g| g.name());
+        let mut generator = Generator::ALL
+            .iter()
+            .position(|g| *g == params.generator)
+            .unwrap_or(0);
+        ui.combo_box(hash!(), "generator", &generators, &mut generator);
+        params.generator = Generator::ALL[generator];
+
+        let mut min_rooms = params.min_rooms as f32;
+        let mut max_rooms = params.max_rooms as f32;
+        ui.slider(hash!(), "min rooms", 1.0..30.0, &mut min_rooms);
+        ui.slider(hash!(), "max rooms", 1.0..30.0, &mut max_rooms);
+        params.min_rooms = min_rooms.round() as usize;
+        params.max_rooms = (max_rooms.round() as usize).max(params.min_rooms);
+
+        let room_sizes = RoomSizes::ALL.map(|r| r.name());
+        let mut room_size = RoomSizes::ALL
+            .iter()
+            .position(|r| *r == params.room_sizes)
+            .unwrap_or(0);
+        ui.combo_box(hash!(), "room sizes", &room_sizes, &mut room_size);
+        params.room_sizes = RoomSizes::ALL[room_size];
+
+        let room_shapes = RoomShapes::ALL.map(|r| r.name());
+        let mut room_shape = RoomShapes::ALL
+            .iter()
+            .position(|r| *r == params.room_shapes)
+            .unwrap_or(0);
+        ui.combo_box(hash!(), "room shapes", &room_shapes, &mut room_shape);
+        params.room_shapes = RoomShapes::ALL[room_shape];
+        ui.checkbox(hash!(), "vaults", &mut params.vaults);
+
+        let mut floors = params.floors as f32;
+        ui.slider(hash!(), "floors", 1.0..10.0, &mut floors);
+        params.floors = floors.round() as usize;
+
+        let mut corridor_max_length = params.corridor_max_length as f32;
+        ui.slider(
+            hash!(),
+            "corridor length",
+            1.0..60.0,
+            &mut corridor_max_length,
+        );
+        params.corridor_max_length = corridor_max_length.round() as usize;
+
+        ui.slider(
+            hash!(),
+            "door probability",
+            0.0..1.0,
+            &mut params.door_probability,
+        );
+
+        let mut lock_count = params.lock_count as f32;
+        ui.slider(hash!(), "locks", 0.0..8.0, &mut lock_count);
+        params.lock_count = lock_count.round() as usize;
+
+        let mut secret_rooms = params.secret_rooms as f32;
+        ui.slider(hash!(), "secret rooms", 0.0..4.0, &mut secret_rooms);
+        params.secret_rooms = secret_rooms.round() as usize;
+
+        ui.slider(
+            hash!(),
+            "spawn density",
+            0.0..0.3,
+            &mut params.spawn_density,
+        );
+        let mut spawn_min_distance = params.spawn_min_distance as f32;
+        ui.slider(
+            hash!(),
+            "spawn min distance",
+            0.0..30.0,
+            &mut spawn_min_distance,
+        );
+        params.spawn_min_distance = spawn_min_distance.round() as u32;
+
+        ui.label(None, &format!("current seed {}", current_seed));
+        widgets::Editbox::new(hash!(), vec2(PANEL_WIDTH - 20.0, 20.0))
+            .filter(&|c| c.is_ascii_digit())
+            .ui(ui, &mut seed_text);
+
+        regenerate = ui.button(None, "Regenerate");
+    });
+
+    w.params = params;
+    w.panel.seed_text = seed_text;
+    if regenerate {
+        let seed = w
+            .panel
+            .seed_text
+            .parse::<u64>()
+            .unwrap_or_else(|_| level::new_seed());
+        w.regenerate(seed);
+    }
+}
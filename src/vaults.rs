@@ -0,0 +1,125 @@
+use macroquad::{
+    prelude::{vec2, Vec2},
+    rand,
+};
+
+use crate::components::{Room, RoomKind, SpawnKind};
+
+static VAULTS: &str = include_str!("../assets/vaults.txt");
+
+/**
+ * Hand-authored room read from the vault file, including its outer wall.
+ */
+pub struct Vault {
+    pub name: String,
+    pub count: usize,
+    pub rows: Vec<Vec<char>>,
+}
+
+/**
+ * Parses the vault file. Every vault starts with a "vault <name> <count>"
+ * header followed by the rows of its layout.
+ */
+pub fn parse_vaults(text: &str) -> Result<Vec<Vault>, String> {
+    let mut vaults: Vec<Vault> = Vec::new();
+    for line in text.lines().map(|l| l.trim_end()) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("vault ") {
+            let (name, count) = header
+                .rsplit_once(' ')
+                .ok_or_else(|| format!("vault header \"{}\" is missing a count", line))?;
+            let count = count
+                .parse()
+                .map_err(|_| format!("vault {} has an invalid count \"{}\"", name, count))?;
+            vaults.push(Vault {
+                name: name.to_string(),
+                count,
+                rows: Vec::new(),
+            });
+            continue;
+        }
+
+        match vaults.last_mut() {
+            Some(vault) => vault.rows.push(line.chars().collect()),
+            None => return Err(format!("layout row \"{}\" before any vault header", line)),
+        }
+    }
+
+    for vault in vaults.iter() {
+        let w = vault.rows.first().map_or(0, |r| r.len());
+        if vault.rows.len() < 3 || w < 3 || vault.rows.iter().any(|r| r.len() != w) {
+            return Err(format!("vault {} needs a rectangular layout", vault.name));
+        }
+    }
+
+    Ok(vaults)
+}
+
+impl Vault {
+    /**
+     * Returns the layout turned clockwise a number of quarter turns and, if
+     * mirror is set, flipped horizontally afterwards.
+     */
+    pub fn transformed(&self, turns: u8, mirror: bool) -> Vec<Vec<char>> {
+        let mut rows = self.rows.clone();
+        for _ in 0..turns % 4 {
+            let (w, h) = (rows[0].len(), rows.len());
+            rows = (0..w)
+                .map(|x| (0..h).rev().map(|y| rows[y][x]).collect())
+                .collect();
+        }
+        if mirror {
+            rows.iter_mut().for_each(|r| r.reverse());
+        }
+
+        rows
+    }
+
+    /**
+     * Builds a room at pos from a randomly turned and mirrored copy of the
+     * vault, along with the spawns marked in it. The room covers the inside of
+     * the outer wall, its entrances lie on the outer wall.
+     */
+    pub fn stamp(&self, pos: Vec2) -> (Room, Vec<(Vec2, SpawnKind)>) {
+        let turns = rand::gen_range(0, 4) as u8;
+        let mirror = rand::gen_range(0, 2) == 0;
+        let rows = self.transformed(turns, mirror);
+        let (w, h) = (rows[0].len(), rows.len());
+
+        let mut mask = Vec::new();
+        let mut entrances = Vec::new();
+        let mut spawns = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let cell = pos + vec2(x as f32 - 1.0, y as f32 - 1.0);
+                let inside = x > 0 && y > 0 && x < w - 1 && y < h - 1;
+                if inside {
+                    mask.push(matches!(c, '.' | '&' | '$' | '^'));
+                }
+                match c {
+                    '+' => entrances.push(cell),
+                    '&' => spawns.push((cell, SpawnKind::Monster)),
+                    '$' => spawns.push((cell, SpawnKind::Treasure)),
+                    '^' => spawns.push((cell, SpawnKind::Trap)),
+                    _ => (),
+                }
+            }
+        }
+
+        let mut room = Room::new(pos, vec2(w as f32 - 3.0, h as f32 - 3.0), mask);
+        room.kind = RoomKind::Vault;
+        room.entrances = Some(entrances);
+        (room, spawns)
+    }
+}
+
+/**
+ * Returns the vaults every dungeon gets. The vault file is part of the binary,
+ * so a broken file is a bug and panics right away.
+ */
+pub fn vaults() -> Vec<Vault> {
+    parse_vaults(VAULTS).unwrap_or_else(|e| panic!("invalid vault file: {}", e))
+}